@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Map;
+
+/// Everything needed to reproduce the kb-remap setup on another machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    /// The contents of the config file, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+    /// The modifications currently applied to each keyboard.
+    #[serde(default)]
+    pub devices: Vec<DeviceMappings>,
+}
+
+/// The modifications applied to a single keyboard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceMappings {
+    pub vendor_id: u64,
+    pub product_id: u64,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    pub mappings: Vec<Map>,
+}
+
+impl Bundle {
+    /// Read the bundle from a file written by `Bundle::write`.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Returns the bundle as it is written by `Bundle::write`.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Write the bundle to a file as JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?)
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Write the config file from the bundle, creating its directory if
+    /// needed. Does nothing if the bundle has no config.
+    pub fn write_config(&self, path: &Path) -> Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create `{}`", dir.display()))?;
+        }
+        fs::write(path, config).with_context(|| format!("failed to write `{}`", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::types::Key;
+
+    #[test]
+    fn bundle_round_trip() {
+        let dir = std::env::temp_dir().join("kb-remap-test-bundle");
+        let config = "[profile.laptop]\nswap = [\"capslock:escape\"]\n";
+        let bundle = Bundle {
+            config: Some(config.to_owned()),
+            devices: vec![DeviceMappings {
+                vendor_id: 0x5ac,
+                product_id: 0x29c,
+                name: "Magic Keyboard".to_owned(),
+                serial: Some("a8-91-3d-0e-6f-21".to_owned()),
+                mappings: vec![
+                    Map(Key::CapsLock, Key::Escape),
+                    Map(Key::Escape, Key::CapsLock),
+                ],
+            }],
+        };
+
+        let path = dir.join("bundle.json");
+        let config_path = dir.join("kb-remap").join("config.toml");
+        fs::create_dir_all(&dir).unwrap();
+        bundle.write(&path).unwrap();
+        let imported = Bundle::read(&path).unwrap();
+        imported.write_config(&config_path).unwrap();
+        let result = (
+            imported,
+            fs::read_to_string(&config_path).unwrap(),
+            Config::read(&config_path).map(|c| c.profiles.len()),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.0, bundle);
+        assert_eq!(result.1, config);
+        assert_eq!(result.2.unwrap(), 1);
+    }
+}
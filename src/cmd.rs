@@ -1,6 +1,9 @@
+use std::io;
 use std::process;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
+
+use crate::Error;
 
 pub trait CommandExt {
     /// Run the command return the standard output as a UTF-8 string.
@@ -10,11 +13,19 @@ pub trait CommandExt {
 impl CommandExt for process::Command {
     /// Run the command return the standard output as a UTF-8 string.
     fn output_text(&mut self) -> Result<String> {
-        let output = self
-            .output()
-            .with_context(|| format!("could not execute subprocess: `{:?}`", self))?;
+        let output = match self.output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let program = self.get_program().to_string_lossy().into_owned();
+                return Err(Error::HidutilNotFound(program).into());
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("could not execute subprocess: `{:?}`", self))
+            }
+        };
         if !output.status.success() {
-            bail!(format_error_msg(self, output));
+            return Err(Error::CommandFailed(format_error_msg(self, output)).into());
         }
         String::from_utf8(output.stdout).context("failed to parse stdout")
     }
@@ -37,3 +48,20 @@ fn format_error_msg(cmd: &process::Command, output: process::Output) -> String {
     }
     msg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_text_not_found() {
+        let err = process::Command::new("/nonexistent/hidutil")
+            .output_text()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`/nonexistent/hidutil` not found, kb-remap only works on macOS where `hidutil` is \
+             available"
+        );
+    }
+}
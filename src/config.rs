@@ -0,0 +1,614 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::hex;
+use crate::selector::Selector;
+use crate::types::Mappings;
+
+/// The configuration file containing named profiles.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default, rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default, rename = "alias")]
+    pub aliases: BTreeMap<String, Alias>,
+    /// Groups of aliases, each written as `@NAME`.
+    #[serde(default, rename = "group")]
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+/// Defaults for command line options, used when they are not given on the
+/// command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    #[serde(default)]
+    pub json: Option<bool>,
+    #[serde(default)]
+    pub quiet: Option<bool>,
+    #[serde(default)]
+    pub verbose: Option<bool>,
+    #[serde(default)]
+    pub no_color: Option<bool>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub vendor_id: Option<u64>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub product_id: Option<u64>,
+}
+
+/// A keyboard selection and mappings that can be applied by name.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The hostname of the Mac the profile is for, used by `--host-profile`.
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub vendor_id: Option<u64>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub product_id: Option<u64>,
+    #[serde(default)]
+    pub swap: Vec<Mappings>,
+    #[serde(default)]
+    pub map: Vec<Mappings>,
+}
+
+/// A name for a keyboard selection.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Alias {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub vendor_id: Option<u64>,
+    #[serde(default, deserialize_with = "hex::deserialize_option")]
+    pub product_id: Option<u64>,
+}
+
+impl Alias {
+    /// Returns the keyboard selection.
+    pub fn selector(&self) -> Selector {
+        Selector {
+            names: self.name.iter().cloned().collect(),
+            vendor_id: self.vendor_id,
+            product_ids: self.product_id.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Config {
+    /// The contents of a new configuration file.
+    pub const TEMPLATE: &'static str = r#"# kb-remap configuration file
+#
+# Defaults for options that are not given on the command line.
+#
+# [defaults]
+# json = true
+# quiet = true
+# no_color = true
+# name = "USB Keyboard"
+#
+# Profiles are applied with `kb-remap --profile NAME`.
+#
+# [profile.laptop]
+# name = "Apple Internal Keyboard / Trackpad"
+# swap = ["capslock:escape"]
+#
+# Profiles scoped to a hostname are applied on that Mac with
+# `kb-remap --host-profile`.
+#
+# [profile.work]
+# hostname = "air-m3"
+# map = ["capslock:escape"]
+#
+# Aliases name a keyboard, and groups of them are applied to with
+# `kb-remap --group NAME`.
+#
+# [alias.k2]
+# name = "Keychron K2"
+#
+# [group]
+# all-externals = ["@k2"]
+"#;
+
+    /// Returns the default path of the configuration file.
+    ///
+    /// This is `$XDG_CONFIG_HOME/kb-remap/config.toml`, falling back to
+    /// `~/.config/kb-remap/config.toml`.
+    pub fn default_path() -> Result<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".config"))
+                .ok_or_else(|| anyhow!("failed to determine home directory"))?,
+        };
+        Ok(dir.join("kb-remap").join("config.toml"))
+    }
+
+    /// Read and parse the configuration file at the given path.
+    ///
+    /// Files with a `.yaml` or `.yml` extension are parsed as YAML, and any
+    /// other file as TOML.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        let config = if is_yaml(path) {
+            Self::parse_yaml(&contents)
+        } else {
+            Self::parse(&contents)
+        };
+        config.with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Read and parse the configuration file at the given path, or return the
+    /// default configuration if it does not exist.
+    pub fn read_if_exists(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::read(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Parse the configuration from TOML.
+    pub fn parse(s: &str) -> Result<Self> {
+        let config: Self = toml::from_str(s)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Parse the configuration from YAML.
+    #[cfg(feature = "yaml")]
+    pub fn parse_yaml(s: &str) -> Result<Self> {
+        let config: Self = serde_yaml::from_str(s)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    /// Parse the configuration from YAML.
+    #[cfg(not(feature = "yaml"))]
+    pub fn parse_yaml(_: &str) -> Result<Self> {
+        bail!("YAML config files are only supported when built with the `yaml` feature")
+    }
+
+    /// Check the parts of the configuration that can't be checked while
+    /// deserializing it.
+    fn check(&self) -> Result<()> {
+        if self.defaults.quiet == Some(true) && self.defaults.verbose == Some(true) {
+            bail!("`quiet` and `verbose` cannot both be set in the defaults");
+        }
+        for (group, members) in &self.groups {
+            for member in members {
+                let Some(alias) = member.strip_prefix('@') else {
+                    bail!(
+                        "expected group `{}` member `{}` to be an alias starting with `@`",
+                        group,
+                        member
+                    );
+                };
+                if !self.aliases.contains_key(alias) {
+                    bail!("group `{}` refers to unknown alias `{}`", group, member);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a mapping to the `map` list of the named profile in the TOML
+    /// configuration, creating the profile if it does not exist.
+    ///
+    /// The rest of the configuration is kept as it is, including comments and
+    /// formatting.
+    pub fn append_map(s: &str, profile: &str, mapping: &str) -> Result<String> {
+        let mut doc: toml_edit::Document = s.parse()?;
+        let profiles = doc
+            .entry("profile")
+            .or_insert_with(|| {
+                let mut table = toml_edit::Table::new();
+                // only write the `[profile.NAME]` headers
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_mut()
+            .context("expected `profile` to be a table")?;
+        let table = profiles
+            .entry(profile)
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .with_context(|| format!("expected `profile.{}` to be a table", profile))?;
+        table
+            .entry("map")
+            .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+            .as_array_mut()
+            .with_context(|| format!("expected `profile.{}.map` to be an array", profile))?
+            .push(mapping);
+        let s = doc.to_string();
+        Self::parse(&s)?;
+        Ok(s)
+    }
+
+    /// Returns the profile with the given name.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let names: Vec<_> = self.profiles.keys().map(|n| format!("`{}`", n)).collect();
+            match names.as_slice() {
+                [] => anyhow!("unknown profile `{}`, no profiles are defined", name),
+                names => anyhow!(
+                    "unknown profile `{}`, expected one of {}",
+                    name,
+                    names.join(", ")
+                ),
+            }
+        })
+    }
+
+    /// Returns the aliases in the group with the given name, along with their
+    /// names.
+    pub fn group(&self, name: &str) -> Result<Vec<(&str, &Alias)>> {
+        let members = self.groups.get(name).ok_or_else(|| {
+            let names: Vec<_> = self.groups.keys().map(|n| format!("`{}`", n)).collect();
+            match names.as_slice() {
+                [] => anyhow!("unknown group `{}`, no groups are defined", name),
+                names => anyhow!(
+                    "unknown group `{}`, expected one of {}",
+                    name,
+                    names.join(", ")
+                ),
+            }
+        })?;
+        // the members are checked when parsing
+        Ok(members
+            .iter()
+            .filter_map(|m| self.aliases.get_key_value(&m[1..]))
+            .map(|(name, alias)| (name.as_str(), alias))
+            .collect())
+    }
+
+    /// Returns the name of the profile scoped to the given hostname, along
+    /// with the profile.
+    pub fn host_profile(&self, hostname: &str) -> Result<(&str, &Profile)> {
+        let matches: Vec<_> = self
+            .profiles
+            .iter()
+            .filter(|(_, profile)| is_host(profile, hostname))
+            .collect();
+        match matches.as_slice() {
+            [] => bail!("no profile is scoped to the hostname `{}`", hostname),
+            [(name, profile)] => Ok((name.as_str(), profile)),
+            matches => {
+                let names: Vec<_> = matches.iter().map(|(n, _)| format!("`{}`", n)).collect();
+                bail!(
+                    "multiple profiles are scoped to the hostname `{}`: {}",
+                    hostname,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// Whether the profile is scoped to the hostname.
+///
+/// Hostnames are compared ignoring case, and a hostname with a domain such as
+/// `air-m3.local` also matches a profile scoped to `air-m3`.
+fn is_host(profile: &Profile, hostname: &str) -> bool {
+    profile.hostname.as_deref().is_some_and(|h| {
+        hostname.eq_ignore_ascii_case(h)
+            || hostname
+                .split_once('.')
+                .is_some_and(|(host, _)| host.eq_ignore_ascii_case(h))
+    })
+}
+
+/// Whether the config file is YAML, going by its extension.
+pub fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("yaml" | "yml")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::{Key, Map};
+
+    #[test]
+    fn config_parse() {
+        let config = Config::parse(
+            r#"
+[profile.laptop]
+name = "Apple Internal Keyboard / Trackpad"
+swap = ["capslock:escape", "lcommand:loption"]
+
+[profile.usb]
+vendor_id = "0xc45"
+product_id = "0x7692"
+map = ["a:b"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.profile("laptop").unwrap(),
+            &Profile {
+                hostname: None,
+                name: Some("Apple Internal Keyboard / Trackpad".to_owned()),
+                vendor_id: None,
+                product_id: None,
+                swap: vec![
+                    Mappings(vec![Map(Key::CapsLock, Key::Escape)]),
+                    Mappings(vec![Map(Key::LeftCommand, Key::LeftOption)]),
+                ],
+                map: vec![],
+            }
+        );
+        assert_eq!(
+            config.profile("usb").unwrap(),
+            &Profile {
+                hostname: None,
+                name: None,
+                vendor_id: Some(0xc45),
+                product_id: Some(0x7692),
+                swap: vec![],
+                map: vec![Mappings(vec![Map(Key::Char('a'), Key::Char('b'))])],
+            }
+        );
+        assert_eq!(
+            config.profile("desktop").unwrap_err().to_string(),
+            "unknown profile `desktop`, expected one of `laptop`, `usb`"
+        );
+    }
+
+    #[test]
+    fn config_parse_defaults() {
+        let config = Config::parse(
+            r#"
+[defaults]
+json = true
+quiet = true
+vendor_id = "0xc45"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.defaults,
+            Defaults {
+                json: Some(true),
+                quiet: Some(true),
+                verbose: None,
+                no_color: None,
+                name: None,
+                vendor_id: Some(0xc45),
+                product_id: None,
+            }
+        );
+        assert!(config.profiles.is_empty());
+        assert_eq!(
+            Config::read_if_exists(Path::new("/nonexistent/config.toml"))
+                .unwrap()
+                .defaults,
+            Defaults::default()
+        );
+        assert_eq!(
+            Config::parse("[defaults]\nquiet = true\nverbose = true\n")
+                .unwrap_err()
+                .to_string(),
+            "`quiet` and `verbose` cannot both be set in the defaults"
+        );
+    }
+
+    #[test]
+    fn config_append_map() {
+        let config = r#"# my keyboards
+[profile.laptop]
+swap = ["capslock:escape"] # muscle memory
+
+[profile.usb]
+vendor_id = "0xc45"
+map = ["a:b"]
+"#;
+        let s = Config::append_map(config, "usb", "c:d").unwrap();
+        assert_eq!(
+            s,
+            r#"# my keyboards
+[profile.laptop]
+swap = ["capslock:escape"] # muscle memory
+
+[profile.usb]
+vendor_id = "0xc45"
+map = ["a:b", "c:d"]
+"#
+        );
+
+        let s = Config::append_map(&s, "laptop", "lcommand:loption").unwrap();
+        let config = Config::parse(&s).unwrap();
+        assert_eq!(
+            config.profile("laptop").unwrap().map,
+            [Mappings(vec![Map(Key::LeftCommand, Key::LeftOption)])]
+        );
+        assert_eq!(config.profile("usb").unwrap().map.len(), 2);
+
+        let s = Config::append_map("", "desk", "capslock:escape").unwrap();
+        assert_eq!(s, "[profile.desk]\nmap = [\"capslock:escape\"]\n");
+
+        let err = Config::append_map("", "desk", "capslock").unwrap_err();
+        assert!(err.to_string().contains("colon not found"), "{}", err);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn config_parse_yaml() {
+        let toml = Config::parse(
+            r#"
+[defaults]
+json = true
+
+[profile.laptop]
+name = "Apple Internal Keyboard / Trackpad"
+swap = ["capslock:escape"]
+
+[profile.usb]
+vendor_id = "0xc45"
+product_id = "0x7692"
+map = ["a:b", "c:d"]
+"#,
+        )
+        .unwrap();
+        let yaml = Config::parse_yaml(
+            r#"
+defaults:
+  json: true
+profile:
+  laptop:
+    name: Apple Internal Keyboard / Trackpad
+    swap: ["capslock:escape"]
+  usb:
+    vendor_id: "0xc45"
+    product_id: "0x7692"
+    map:
+      - a:b
+      - c:d
+"#,
+        )
+        .unwrap();
+        assert_eq!(yaml.defaults, toml.defaults);
+        assert_eq!(yaml.profiles, toml.profiles);
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    #[test]
+    fn config_parse_yaml_unsupported() {
+        assert_eq!(
+            Config::parse_yaml("defaults: {}\n")
+                .unwrap_err()
+                .to_string(),
+            "YAML config files are only supported when built with the `yaml` feature"
+        );
+    }
+
+    #[test]
+    fn config_host_profile() {
+        let config = Config::parse(
+            r#"
+[profile.home]
+hostname = "studio"
+swap = ["capslock:escape"]
+
+[profile.work]
+hostname = "air-m3"
+map = ["a:b"]
+
+[profile.usb]
+name = "USB Keyboard"
+"#,
+        )
+        .unwrap();
+        let (name, profile) = config.host_profile("air-m3").unwrap();
+        assert_eq!(name, "work");
+        assert_eq!(
+            profile.map,
+            [Mappings(vec![Map(Key::Char('a'), Key::Char('b'))])]
+        );
+        assert_eq!(config.host_profile("Studio.local").unwrap().0, "home");
+        assert_eq!(
+            config.host_profile("mini").unwrap_err().to_string(),
+            "no profile is scoped to the hostname `mini`"
+        );
+
+        let config = Config::parse(
+            "[profile.a]\nhostname = \"air-m3\"\n[profile.b]\nhostname = \"air-m3\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.host_profile("air-m3").unwrap_err().to_string(),
+            "multiple profiles are scoped to the hostname `air-m3`: `a`, `b`"
+        );
+    }
+
+    #[test]
+    fn config_group() {
+        let config = Config::parse(
+            r#"
+[alias.k2]
+name = "Keychron K2"
+
+[alias.hhkb]
+vendor_id = "0x4fe"
+product_id = "0x21"
+
+[group]
+all-externals = ["@k2", "@hhkb"]
+"#,
+        )
+        .unwrap();
+        let members = config.group("all-externals").unwrap();
+        let names: Vec<_> = members.iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, ["k2", "hhkb"]);
+        assert_eq!(members[0].1.selector().names, ["Keychron K2"]);
+        assert_eq!(
+            members[1].1.selector(),
+            Selector {
+                vendor_id: Some(0x4fe),
+                product_ids: vec![0x21],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            config.group("laptops").unwrap_err().to_string(),
+            "unknown group `laptops`, expected one of `all-externals`"
+        );
+
+        assert_eq!(
+            Config::parse("[group]\nall = [\"k2\"]\n")
+                .unwrap_err()
+                .to_string(),
+            "expected group `all` member `k2` to be an alias starting with `@`"
+        );
+        assert_eq!(
+            Config::parse("[group]\nall = [\"@k2\"]\n")
+                .unwrap_err()
+                .to_string(),
+            "group `all` refers to unknown alias `@k2`"
+        );
+    }
+
+    #[test]
+    fn config_is_yaml() {
+        assert!(is_yaml(Path::new("config.yaml")));
+        assert!(is_yaml(Path::new("config.yml")));
+        assert!(!is_yaml(Path::new("config.toml")));
+        assert!(!is_yaml(Path::new("config")));
+    }
+
+    #[test]
+    fn config_parse_template() {
+        let config = Config::parse(Config::TEMPLATE).unwrap();
+        assert_eq!(config.defaults, Defaults::default());
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn config_parse_invalid_mapping() {
+        let err = Config::parse("[profile.laptop]\nswap = [\"capslock\"]\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("invalid mapping `capslock`: colon not found"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt::Write;
+
+use crate::types::{Key, Map};
+
+/// The rows of keys on an ANSI 60% keyboard.
+const LAYOUT: &[&[Key]] = &[
+    &[
+        Key::Char('`'),
+        Key::Char('1'),
+        Key::Char('2'),
+        Key::Char('3'),
+        Key::Char('4'),
+        Key::Char('5'),
+        Key::Char('6'),
+        Key::Char('7'),
+        Key::Char('8'),
+        Key::Char('9'),
+        Key::Char('0'),
+        Key::Char('-'),
+        Key::Char('='),
+        Key::Delete,
+    ],
+    &[
+        Key::Char('\t'),
+        Key::Char('q'),
+        Key::Char('w'),
+        Key::Char('e'),
+        Key::Char('r'),
+        Key::Char('t'),
+        Key::Char('y'),
+        Key::Char('u'),
+        Key::Char('i'),
+        Key::Char('o'),
+        Key::Char('p'),
+        Key::Char('['),
+        Key::Char(']'),
+        Key::Char('\\'),
+    ],
+    &[
+        Key::CapsLock,
+        Key::Char('a'),
+        Key::Char('s'),
+        Key::Char('d'),
+        Key::Char('f'),
+        Key::Char('g'),
+        Key::Char('h'),
+        Key::Char('j'),
+        Key::Char('k'),
+        Key::Char('l'),
+        Key::Char(';'),
+        Key::Char('\''),
+        Key::Return,
+    ],
+    &[
+        Key::LeftShift,
+        Key::Char('z'),
+        Key::Char('x'),
+        Key::Char('c'),
+        Key::Char('v'),
+        Key::Char('b'),
+        Key::Char('n'),
+        Key::Char('m'),
+        Key::Char(','),
+        Key::Char('.'),
+        Key::Char('/'),
+        Key::RightShift,
+    ],
+    &[
+        Key::Fn,
+        Key::LeftControl,
+        Key::LeftOption,
+        Key::LeftCommand,
+        Key::Char(' '),
+        Key::RightCommand,
+        Key::RightOption,
+        Key::RightControl,
+    ],
+];
+
+/// Render a diagram of a keyboard with each remapped key annotated with the
+/// key it is remapped to.
+///
+/// Keys that are not on the keyboard diagram are listed below it.
+pub fn render(mappings: &[Map]) -> String {
+    let mut s = String::new();
+    for row in LAYOUT {
+        for (i, key) in row.iter().enumerate() {
+            if i > 0 {
+                s.push(' ');
+            }
+            match mappings.iter().find(|Map(src, _)| same(src, key)) {
+                Some(Map(_, dst)) => write!(s, "[{}→{}]", label(key), label(dst)).unwrap(),
+                None => write!(s, "[{}]", label(key)).unwrap(),
+            }
+        }
+        s.push('\n');
+    }
+
+    let others: Vec<_> = mappings
+        .iter()
+        .filter(|Map(src, _)| !LAYOUT.iter().flat_map(|r| r.iter()).any(|k| same(src, k)))
+        .collect();
+    if !others.is_empty() {
+        s.push_str("\nNot shown:\n");
+        for Map(src, dst) in others {
+            writeln!(s, "  {} → {}", label(src), label(dst)).unwrap();
+        }
+    }
+    s
+}
+
+/// Whether the keys have the same usage, e.g. `a` and `A`.
+fn same(a: &Key, b: &Key) -> bool {
+    a.usage_page_id() == b.usage_page_id() && a.usage_id().is_some() && a.usage_id() == b.usage_id()
+}
+
+/// Returns a short printable label for the key.
+fn label(key: &Key) -> String {
+    match key {
+        Key::Char('\t') => "tab".to_owned(),
+        Key::Char(' ') => "space".to_owned(),
+        key => key.code(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mappings = [
+            Map(Key::CapsLock, Key::Escape),
+            Map(Key::Char('A'), Key::Char('b')),
+            Map(Key::F(13), Key::Lang(1)),
+        ];
+        assert_eq!(
+            render(&mappings),
+            "\
+[`] [1] [2] [3] [4] [5] [6] [7] [8] [9] [0] [-] [=] [delete]
+[tab] [q] [w] [e] [r] [t] [y] [u] [i] [o] [p] [[] []] [\\]
+[capslock→escape] [a→b] [s] [d] [f] [g] [h] [j] [k] [l] [;] ['] [return]
+[lshift] [z] [x] [c] [v] [b] [n] [m] [,] [.] [/] [rshift]
+[fn] [lcontrol] [loption] [lcommand] [space] [rcommand] [roption] [rcontrol]
+
+Not shown:
+  f13 → lang1
+"
+        );
+    }
+}
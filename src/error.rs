@@ -0,0 +1,36 @@
+use crate::hid::Device;
+
+/// An error returned by the library API.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The `hidutil` program could not be found, which means this is not
+    /// macOS.
+    #[error("`{0}` not found, kb-remap only works on macOS where `hidutil` is available")]
+    HidutilNotFound(String),
+
+    /// The `hidutil list` output could not be parsed.
+    #[error("failed to parse `hidutil list` output: {0}")]
+    ParseFailed(String),
+
+    /// No keyboard matched the selection.
+    #[error("failed to find device matching name `{0}`")]
+    NoMatch(String),
+
+    /// More than one keyboard matched the selection.
+    #[error("multiple devices matching name `{}`", .0[0].name)]
+    MultipleMatches(Vec<Device>),
+
+    /// Running `hidutil` failed.
+    #[error("{0}")]
+    CommandFailed(String),
+}
+
+impl Error {
+    /// Convert an error from the internals, keeping it if it is already one
+    /// of these and otherwise converting it with the given variant.
+    pub(crate) fn from_anyhow(err: anyhow::Error, variant: fn(String) -> Self) -> Self {
+        err.downcast::<Self>()
+            .unwrap_or_else(|err| variant(format!("{:#}", err)))
+    }
+}
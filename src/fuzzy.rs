@@ -0,0 +1,132 @@
+use anyhow::{bail, Result};
+
+/// The minimum score a name must have to be considered a match.
+const THRESHOLD: f64 = 0.75;
+
+/// The minimum difference in score between the best and second best match.
+const MARGIN: f64 = 0.1;
+
+/// Select the candidate that best matches the query.
+///
+/// Fails if no candidate scores above the threshold or if the top two distinct
+/// candidates are too close to call.
+pub fn best_match<'a, I>(query: &str, candidates: I) -> Result<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut scored: Vec<_> = candidates
+        .into_iter()
+        .map(|c| (score(query, c), c))
+        .filter(|(s, _)| *s >= THRESHOLD)
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.dedup_by(|a, b| a.1 == b.1);
+
+    match scored.as_slice() {
+        [] => bail!("failed to find device matching name `{}`", query),
+        [(s0, c0), (s1, c1), ..] if s0 - s1 < MARGIN => {
+            bail!(
+                "ambiguous device name `{}`, did you mean `{}` or `{}`?",
+                query,
+                c0,
+                c1
+            )
+        }
+        [(_, c), ..] => Ok(c),
+    }
+}
+
+/// Returns a score between 0.0 and 1.0 of how well the query matches the
+/// candidate.
+///
+/// The query is compared against every window of the candidate with the same
+/// length so that a partially remembered name still scores highly.
+pub fn score(query: &str, candidate: &str) -> f64 {
+    let query: Vec<_> = query.to_lowercase().chars().collect();
+    let candidate: Vec<_> = candidate.to_lowercase().chars().collect();
+    if query.is_empty() {
+        return 0.0;
+    }
+    let distance = if candidate.len() <= query.len() {
+        levenshtein(&query, &candidate)
+    } else {
+        candidate
+            .windows(query.len())
+            .map(|w| levenshtein(&query, w))
+            .min()
+            .unwrap()
+    };
+    1.0 - distance as f64 / query.len() as f64
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev: Vec<_> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: &[&str] = &[
+        "BTM",
+        "Magic Mouse",
+        "Apple Internal Keyboard / Trackpad",
+        "Keyboard Backlight",
+        "USB Keyboard",
+    ];
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score("usb keyboard", "USB Keyboard"), 1.0);
+        assert_eq!(score("internal", "Apple Internal Keyboard / Trackpad"), 1.0);
+        assert_eq!(score("", "USB Keyboard"), 0.0);
+        assert!(score("magic mose", "Magic Mouse") > THRESHOLD);
+        assert!(score("yubikey", "Magic Mouse") < THRESHOLD);
+    }
+
+    #[test]
+    fn test_best_match_typo() {
+        assert_eq!(
+            best_match("apple internl keybaord", NAMES.iter().copied()).unwrap(),
+            "Apple Internal Keyboard / Trackpad"
+        );
+        assert_eq!(
+            best_match("usb keybord", NAMES.iter().copied()).unwrap(),
+            "USB Keyboard"
+        );
+    }
+
+    #[test]
+    fn test_best_match_duplicate_names() {
+        assert_eq!(
+            best_match("BTM", ["BTM", "BTM", "Magic Mouse"]).unwrap(),
+            "BTM"
+        );
+    }
+
+    #[test]
+    fn test_best_match_ambiguous() {
+        let err = best_match("keyboard", NAMES.iter().copied()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("ambiguous device name `keyboard`"));
+    }
+
+    #[test]
+    fn test_best_match_none() {
+        let err = best_match("yubikey", NAMES.iter().copied()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to find device matching name `yubikey`"
+        );
+    }
+}
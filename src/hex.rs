@@ -1,6 +1,8 @@
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Error, Result};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::Serializer;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Hex(pub u64);
@@ -19,3 +21,32 @@ pub fn parse(s: &str) -> Result<u64> {
         .ok_or_else(|| anyhow!("{} missing prefix `0x`", s))?;
     u64::from_str_radix(h, 16).with_context(|| format!("failed to parse `{}` as hexadecimal", s))
 }
+
+/// Serialize a number as a hexadecimal string.
+pub fn serialize<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&format_args!("{:#x}", value))
+}
+
+/// Serialize an optional number as a hexadecimal string.
+pub fn serialize_option<S>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serialize(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Deserialize an optional number from a hexadecimal string.
+pub fn deserialize_option<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse(&s).map_err(de::Error::custom))
+        .transpose()
+}
@@ -1,188 +1,1743 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::env;
 use std::fmt::Write;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, SystemTime};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use serde::Serialize;
 
 use crate::cmd::CommandExt;
 use crate::hex;
-pub use crate::types::Map;
+pub use crate::types::{Key, Map};
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 pub struct Device {
+    #[serde(serialize_with = "hex::serialize")]
     pub vendor_id: u64,
+    #[serde(serialize_with = "hex::serialize")]
     pub product_id: u64,
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(
+        serialize_with = "hex::serialize_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub location_id: Option<u64>,
+    #[serde(
+        serialize_with = "hex::serialize_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub registry_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<String>,
 }
 
-/// List available HID devices.
-pub fn list() -> Result<Vec<Device>> {
-    let output = process::Command::new("hidutil").arg("list").output_text()?;
-    let devices = parse_hidutil_output(&output).context("failed to parse `hidutil list` output")?;
+/// Whether a device is currently connected or only remembered by macOS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Connected,
+    Remembered,
+}
+
+impl Device {
+    /// Construct a new device from its raw fields.
+    ///
+    /// This is useful for targeting a device that is not currently attached.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::path::Path;
+    ///
+    /// use kb_remap::hid::{self, Device};
+    /// use kb_remap::types::{Key, Map};
+    ///
+    /// let device = Device::new(0xc45, 0x7692, "USB Keyboard").with_location_id(Some(0x14200000));
+    /// let mappings = [Map::new(Key::CapsLock, Key::Escape)];
+    /// let command = hid::dump(Path::new("hidutil"), &Some(device), &mappings).unwrap();
+    /// assert!(command.starts_with("hidutil property"));
+    /// assert!(command.contains(
+    ///     r#"--matching '{"VendorID": 0x0c45, "ProductID": 0x7692, "LocationID": 0x14200000}'"#
+    /// ));
+    /// ```
+    pub fn new(vendor_id: u64, product_id: u64, name: impl Into<String>) -> Self {
+        Self {
+            vendor_id,
+            product_id,
+            name: name.into(),
+            serial: None,
+            manufacturer: None,
+            status: None,
+            location_id: None,
+            registry_id: None,
+            transport: None,
+        }
+    }
+
+    /// Set the serial number of the device.
+    pub fn with_serial(mut self, serial: Option<String>) -> Self {
+        self.serial = serial;
+        self
+    }
+
+    /// Set the manufacturer of the device.
+    pub fn with_manufacturer(mut self, manufacturer: Option<String>) -> Self {
+        self.manufacturer = manufacturer;
+        self
+    }
+
+    /// Set whether the device is connected.
+    pub fn with_status(mut self, status: Option<Status>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Set the location ID of the device.
+    ///
+    /// This distinguishes identical keyboards that are connected at the same
+    /// time.
+    pub fn with_location_id(mut self, location_id: Option<u64>) -> Self {
+        self.location_id = location_id;
+        self
+    }
+
+    /// Set the registry ID of the service to target on the device.
+    pub fn with_registry_id(mut self, registry_id: Option<u64>) -> Self {
+        self.registry_id = registry_id;
+        self
+    }
+
+    /// Set how the device is connected, e.g. `USB` or `Bluetooth`.
+    pub fn with_transport(mut self, transport: Option<String>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Returns the vendor and product ID that identify the device to `hidutil`.
+    pub fn id_key(&self) -> (u64, u64) {
+        (self.vendor_id, self.product_id)
+    }
+}
+
+/// Group the devices by vendor and product ID, joining the names of devices
+/// that share an ID.
+///
+/// `hidutil` can only match devices by ID, so this shows which names are
+/// modified together.
+pub fn group_by_id(devices: &[Device]) -> Vec<Device> {
+    let mut grouped: Vec<Device> = Vec::new();
+    for device in devices {
+        match grouped.iter_mut().find(|d| d.id_key() == device.id_key()) {
+            Some(d) => {
+                if !d.name.split(", ").any(|n| n == device.name) {
+                    d.name.push_str(", ");
+                    d.name.push_str(&device.name);
+                }
+                if d.serial.is_none() {
+                    d.serial = device.serial.clone();
+                }
+            }
+            None => grouped.push(device.clone()),
+        }
+    }
+    grouped
+}
+
+/// Group the devices by vendor ID, in order of the vendor ID and keeping the
+/// order of the devices within each group.
+pub fn group_by_vendor(devices: &[Device]) -> Vec<(u64, Vec<Device>)> {
+    let mut grouped: Vec<(u64, Vec<Device>)> = Vec::new();
+    for device in devices {
+        match grouped.iter_mut().find(|(id, _)| *id == device.vendor_id) {
+            Some((_, group)) => group.push(device.clone()),
+            None => grouped.push((device.vendor_id, vec![device.clone()])),
+        }
+    }
+    grouped.sort_by_key(|(id, _)| *id);
+    grouped
+}
+
+/// Returns a command running the given `hidutil` program.
+fn command(hidutil: &Path) -> process::Command {
+    process::Command::new(hidutil)
+}
+
+/// How long a cached `hidutil list` output is used for.
+const LIST_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Returns the directory that kb-remap keeps its cached files in.
+pub fn cache_dir() -> PathBuf {
+    env::temp_dir().join("kb-remap")
+}
+
+/// Returns the path of the cached `hidutil list` output for the program.
+///
+/// The path is keyed on the `hidutil` program so that the output of one
+/// program, e.g. a fake one given with `--hidutil-path`, is never used for
+/// another.
+pub fn list_cache_path(hidutil: &Path) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    hidutil.hash(&mut hasher);
+    cache_dir().join(format!("hidutil-list-{:016x}.txt", hasher.finish()))
+}
+
+/// Returns the files in the cache directory.
+pub fn cache_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read `{}`", dir.display())),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Remove the files in the cache directory, returning the removed files.
+pub fn clear_cache(dir: &Path) -> Result<Vec<PathBuf>> {
+    let files = cache_files(dir)?;
+    for path in &files {
+        fs::remove_file(path).with_context(|| format!("failed to remove `{}`", path.display()))?;
+    }
+    Ok(files)
+}
+
+/// Returns the unmodified `hidutil list` output, using the output cached at
+/// the path if it is recent enough and caching the output otherwise.
+///
+/// The raw output is cached rather than the parsed devices because some
+/// options use more of it than the devices, e.g. `--list --raw` and
+/// `--explain-device`, and parsing it again is cheap compared to running
+/// `hidutil`.
+pub fn list_raw_cached(hidutil: &Path, cache: &Path) -> Result<String> {
+    if let Some(output) = read_cache(cache, LIST_CACHE_TTL, SystemTime::now()) {
+        return Ok(output);
+    }
+    let output = list_raw(hidutil)?;
+    // failing to write the cache only makes the next invocation slower
+    if let Some(dir) = cache.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(cache, &output);
+    Ok(output)
+}
+
+/// Returns the cached output if it was written no longer than the TTL ago.
+fn read_cache(path: &Path, ttl: Duration, now: SystemTime) -> Option<String> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    if now.duration_since(modified).ok()? > ttl {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Returns the unmodified `hidutil list` output.
+pub fn list_raw(hidutil: &Path) -> Result<String> {
+    command(hidutil).arg("list").output_text()
+}
+
+/// Parse the devices from the `hidutil list` output.
+pub fn parse_list(output: &str) -> Result<Vec<Device>> {
+    parse_hidutil_output(output).context("failed to parse `hidutil list` output")
+}
+
+/// Returns the vendor and product IDs in the `hidutil list` output that were
+/// parsed as decimal because they have no `0x` prefix.
+///
+/// The parser does not report these itself so that the caller can decide
+/// whether they are worth a warning.
+pub fn decimal_ids(output: &str) -> Vec<String> {
+    let Some((header, lines)) = devices_section(output).and_then(|s| s.split_once('\n')) else {
+        return Vec::new();
+    };
+    let mut ids = Vec::new();
+    for row in parse_columns(header, lines) {
+        for h in ["VendorID", "ProductID"] {
+            match row.get(h) {
+                Some(id) if is_decimal_id(id) && !ids.contains(id) => ids.push(id.clone()),
+                _ => {}
+            }
+        }
+    }
+    ids
+}
+
+/// Whether the `hidutil list` output has a section listing devices.
+///
+/// Like [`decimal_ids`] this is left to the caller to warn about.
+pub fn has_devices(output: &str) -> bool {
+    devices_section(output).is_some()
+}
+
+/// Returns every field of each row in the `hidutil list` output that belongs
+/// to the device, in column order.
+///
+/// When the output has both sections each devices row is merged with the
+/// fields of each of its services, such as the location, registry ID,
+/// transport, and usages, giving a row per service.
+pub fn parse_fields(output: &str, device: &Device) -> Vec<Vec<(String, String)>> {
+    let Some(section) = devices_section(output) else {
+        return Vec::new();
+    };
+    let rows = section_fields(section, device);
+    if find_section(output, "Devices:\n").is_none() {
+        // the devices were read from the services section
+        return rows;
+    }
+    let services = services_section(output)
+        .map(|s| section_fields(s, device))
+        .unwrap_or_default();
+    if services.is_empty() {
+        return rows;
+    }
+    let mut merged = Vec::new();
+    for row in &rows {
+        for service in &services {
+            let mut fields = row.clone();
+            for (header, value) in service {
+                if !fields.iter().any(|(h, _)| h == header) {
+                    fields.push((header.clone(), value.clone()));
+                }
+            }
+            merged.push(fields);
+        }
+    }
+    merged
+}
+
+/// Returns every field of each row in the section that belongs to the device,
+/// in column order.
+fn section_fields(section: &str, device: &Device) -> Vec<Vec<(String, String)>> {
+    let Some((header, lines)) = section.split_once('\n') else {
+        return Vec::new();
+    };
+    let mut rows = Vec::new();
+    for mut row in parse_columns(header, lines) {
+        let matches = |h, id| row.get(h).and_then(|v| parse_id(v).ok()) == Some(id);
+        if !matches("VendorID", device.vendor_id) || !matches("ProductID", device.product_id) {
+            continue;
+        }
+        if let Some(location_id) = device.location_id {
+            if row.contains_key("LocationID") && !matches("LocationID", location_id) {
+                continue;
+            }
+        }
+        // the services section might not have the product name
+        let name = row.get("Product").map(|n| n.replace('\n', " "));
+        if !device.name.is_empty() && name.as_ref().is_some_and(|n| *n != device.name) {
+            continue;
+        }
+        if device.serial.is_some()
+            && row.contains_key("SerialNumber")
+            && row.get("SerialNumber").and_then(|s| parse_maybe(s)) != device.serial
+        {
+            continue;
+        }
+        let fields = header
+            .split_whitespace()
+            .map(|h| (h.to_owned(), row.remove(h).unwrap_or_default()))
+            .collect();
+        rows.push(fields);
+    }
+    rows
+}
+
+/// Parse the devices from the `hidutil list` output.
+///
+/// The output can have a `Devices:` section, a `Services:` section, or both.
+/// Keyboards that appear more than once, e.g. in both sections, are only
+/// returned once. If there is no section listing devices an empty list is
+/// returned, use [`has_devices`] to tell this apart from an empty section.
+pub fn parse_hidutil_output(output: &str) -> Result<Vec<Device>> {
+    match devices_section(output) {
+        Some(section) => {
+            let services = parse_services(output);
+            let mut devices = with_locations(parse_section(section)?, &services);
+            // when the devices were read from the services section every
+            // device has a service, so the status would not tell anything
+            if find_section(output, "Devices:\n").is_some() && !services.is_empty() {
+                let ids: Vec<_> = services.iter().map(Service::id_key).collect();
+                devices = devices
+                    .into_iter()
+                    .map(|d| {
+                        let status = status(&d, &ids);
+                        d.with_status(Some(status))
+                    })
+                    .collect();
+            }
+            Ok(devices)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Returns the section of the output listing the devices.
+fn devices_section(output: &str) -> Option<&str> {
+    // first find the devices section, falling back to the services section
+    // if it also has the product name since the devices section is missing
+    // on some systems
+    find_section(output, "Devices:\n").or_else(|| {
+        find_section(output, "Services:\n").filter(|s| {
+            s.lines()
+                .next()
+                .is_some_and(|h| h.split_whitespace().any(|c| c == "Product"))
+        })
+    })
+}
+
+/// A row of the services section of the `hidutil list` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Service {
+    vendor_id: u64,
+    product_id: u64,
+    name: Option<String>,
+    location_id: Option<u64>,
+    registry_id: Option<u64>,
+    transport: Option<String>,
+}
+
+impl Service {
+    fn id_key(&self) -> (u64, u64) {
+        (self.vendor_id, self.product_id)
+    }
+
+    /// Whether the service belongs to the device.
+    fn is_for(&self, device: &Device) -> bool {
+        self.id_key() == device.id_key()
+            && self.name.as_ref().is_none_or(|name| *name == device.name)
+    }
+}
+
+/// Returns the services section of the `hidutil list` output, without the
+/// devices section if it comes after it.
+fn services_section(output: &str) -> Option<&str> {
+    let section = find_section(output, "Services:\n")?;
+    match section.find("Devices:\n") {
+        Some(i) => Some(&section[..i]),
+        None => Some(section),
+    }
+}
+
+/// Parse the services section of the `hidutil list` output.
+fn parse_services(output: &str) -> Vec<Service> {
+    let Some((header, lines)) = services_section(output).and_then(|s| s.split_once('\n')) else {
+        return Vec::new();
+    };
+    parse_columns(header, lines)
+        .into_iter()
+        .filter_map(|row| {
+            let id = |h| row.get(h).and_then(|v| parse_id(v).ok());
+            Some(Service {
+                vendor_id: id("VendorID")?,
+                product_id: id("ProductID")?,
+                name: row
+                    .get("Product")
+                    .and_then(|s| parse_maybe(s))
+                    .map(|n| n.replace('\n', " ")),
+                location_id: id("LocationID"),
+                registry_id: id("RegistryID"),
+                transport: row.get("Transport").and_then(|s| parse_maybe(s)),
+            })
+        })
+        .collect()
+}
+
+/// Set the location ID and transport of each device from its services,
+/// splitting devices that have services at multiple locations, i.e. identical
+/// keyboards.
+fn with_locations(devices: Vec<Device>, services: &[Service]) -> Vec<Device> {
+    let mut result = Vec::new();
+    for d in devices {
+        let services: Vec<_> = services.iter().filter(|s| s.is_for(&d)).collect();
+        let transport = services.iter().find_map(|s| s.transport.clone());
+        // the same keyboard can be connected with different transports, e.g.
+        // USB and Bluetooth, at different locations
+        let mut locations: Vec<_> = services
+            .iter()
+            .filter_map(|s| Some((s.location_id?, s.transport.clone())))
+            .collect();
+        locations.sort_unstable();
+        locations.dedup_by_key(|(location, _)| *location);
+        if locations.is_empty() {
+            result.push(d.with_transport(transport));
+        } else {
+            for (location, t) in locations {
+                result.push(
+                    d.clone()
+                        .with_location_id(Some(location))
+                        .with_transport(t.or_else(|| transport.clone())),
+                );
+            }
+        }
+    }
+    result
+}
+
+/// Returns the device for the service with the given registry ID.
+pub fn find_service(output: &str, registry_id: u64) -> Result<Device> {
+    let service = parse_services(output)
+        .into_iter()
+        .find(|s| s.registry_id == Some(registry_id))
+        .with_context(|| {
+            format!(
+                "failed to find service with registry ID `{:#x}`",
+                registry_id
+            )
+        })?;
+    Ok(Device::new(
+        service.vendor_id,
+        service.product_id,
+        service.name.unwrap_or_default(),
+    )
+    .with_registry_id(Some(registry_id)))
+}
+
+/// Derive whether the device is connected from the services in the
+/// `hidutil list` output.
+///
+/// `hidutil` does not report this directly, but macOS only creates services
+/// for devices that are connected, so a device without a service with the
+/// same vendor and product ID is assumed to only be remembered.
+fn status(device: &Device, services: &[(u64, u64)]) -> Status {
+    if services.contains(&device.id_key()) {
+        Status::Connected
+    } else {
+        Status::Remembered
+    }
+}
+
+/// Returns the output following the given section header.
+fn find_section<'a>(output: &'a str, header: &str) -> Option<&'a str> {
+    output.find(header).map(|i| &output[i + header.len()..])
+}
+
+fn parse_section(output: &str) -> Result<Vec<Device>> {
+    let (header, lines) = output.split_once('\n').context("expected header")?;
+
+    let mut devices = Vec::new();
+    for row in parse_columns(header, lines) {
+        let field = |h| {
+            row.get(h)
+                .map(String::as_str)
+                .with_context(|| format!("expected `{}` column", h))
+        };
+        let name = match parse_maybe(field("Product")?) {
+            Some(name) => name.replace('\n', " "),
+            None => continue,
+        };
+        let vendor_id = parse_id(field("VendorID")?)?;
+        let product_id = parse_id(field("ProductID")?)?;
+
+        let serial = row.get("SerialNumber").and_then(|s| parse_maybe(s));
+        let manufacturer = row.get("Manufacturer").and_then(|s| parse_maybe(s));
+
+        devices.push(
+            Device::new(vendor_id, product_id, name)
+                .with_serial(serial)
+                .with_manufacturer(manufacturer),
+        );
+    }
+
+    devices.sort();
+    devices.dedup();
+
     Ok(devices)
 }
 
-fn parse_hidutil_output(mut output: &str) -> Result<Vec<Device>> {
-    let mut devices = Vec::new();
+/// Parse a vendor or product ID, falling back to decimal if the value does not
+/// have the `0x` prefix.
+///
+/// IDs are compared by value so that the same ID is equal regardless of how it
+/// is formatted, e.g. `0x05AC` in one section and `0x5ac` in another.
+fn parse_id(s: &str) -> Result<u64> {
+    if let Some(h) = s.strip_prefix("0X") {
+        return hex::parse(&format!("0x{}", h));
+    }
+    if s.starts_with("0x") {
+        return hex::parse(s);
+    }
+    s.parse()
+        .with_context(|| format!("failed to parse `{}` as hexadecimal or decimal", s))
+}
+
+/// Whether the ID has no `0x` prefix and so is parsed as decimal.
+fn is_decimal_id(s: &str) -> bool {
+    !s.starts_with("0x") && !s.starts_with("0X") && s.parse::<u64>().is_ok()
+}
+
+/// Parse the lines of a `hidutil` table into a map of header -> value per row.
+///
+/// The header indices are used to know where columns start and end. The last
+/// column extends to the next newline, so if a value in it is wrapped then
+/// the remainder of the value is found at the start of the next line.
+fn parse_columns(header: &str, mut lines: &str) -> Vec<HashMap<String, String>> {
+    let indices: Vec<_> = split_whitespace_indices(header)
+        .map(|(header, i)| Some((header.trim(), i)))
+        .chain([None])
+        .collect();
+
+    let mut rows = Vec::new();
+    if indices.len() < 2 {
+        return rows;
+    }
+    while !lines.is_empty() {
+        // skip over any leading newlines
+        if lines.starts_with('\n') {
+            lines = &lines[1..];
+            continue;
+        }
+
+        let mut line_end = 0;
+
+        let row = indices
+            .windows(2)
+            .map(|w| match *w {
+                [Some((header, m)), Some((_, n))] => {
+                    let value = get_trimmed(lines, m, n);
+                    (header.to_owned(), value.to_owned())
+                }
+                [Some((header, m)), None] => {
+                    line_end = lines
+                        .get(m..)
+                        .and_then(|rest| rest.find('\n'))
+                        .map(|i| m + i + 1)
+                        .unwrap_or(lines.len());
+                    let value = get_trimmed(lines, m, line_end);
+                    (header.to_owned(), value.to_owned())
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+        rows.push(row);
+
+        lines = &lines[line_end..];
+    }
+    rows
+}
+
+/// Returns the trimmed substring between the indices, clamped to the length
+/// of the string.
+fn get_trimmed(s: &str, m: usize, n: usize) -> &str {
+    let n = n.min(s.len());
+    s.get(m.min(n)..n).unwrap_or_default().trim()
+}
+
+/// Apply the modifications to the device.
+pub fn apply(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<()> {
+    // first do a harmless read of the property so that we fail early if
+    // `hidutil` can't access the device at all
+    current(hidutil, device).context("failed to read the current key mapping")?;
+    set(hidutil, device, mappings)
+}
+
+/// Apply the modifications to the device unless they are already exactly the
+/// current ones, returning whether they were applied.
+///
+/// The current mappings can only be compared for a selected device, since for
+/// every device they only show one of them.
+pub fn apply_if_changed(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<bool> {
+    let current = current(hidutil, device).context("failed to read the current key mapping")?;
+    if device.is_some() && is_applied(&current, mappings)? {
+        return Ok(false);
+    }
+    set(hidutil, device, mappings)?;
+    Ok(true)
+}
+
+/// Set the `UserKeyMapping` property of the device.
+fn set(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<()> {
+    let mut cmd = command(hidutil);
+    cmd.arg("property");
+    if let Some(d) = device {
+        cmd.arg("--matching").arg(dump_matching_option(d));
+    }
+    cmd.arg("--set")
+        .arg(dump_set_option(mappings)?)
+        .output_text()
+        .map_err(|err| {
+            if is_permission_error(&err) {
+                err.context(
+                    "failed to set the key mapping due to insufficient permissions, try \
+                     running with `sudo` or allowing your terminal Input Monitoring access \
+                     in System Settings > Privacy & Security",
+                )
+            } else {
+                err
+            }
+        })?;
+    Ok(())
+}
+
+/// Apply the fn key modifications to the device using the `AppleFnUsageMap`
+/// property.
+///
+/// Unlike `apply` the current value is not read first since `hidutil` does not
+/// report it for every keyboard.
+pub fn apply_apple_fn(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<()> {
+    let mut cmd = command(hidutil);
+    cmd.arg("property");
+    if let Some(d) = device {
+        cmd.arg("--matching").arg(dump_matching_option(d));
+    }
+    cmd.arg("--set")
+        .arg(dump_apple_fn_option(mappings)?)
+        .output_text()
+        .context("failed to set the fn key mapping")?;
+    Ok(())
+}
+
+/// Whether the current mappings are already exactly the given mappings.
+pub fn is_applied(current: &[(u64, u64)], mappings: &[Map]) -> Result<bool> {
+    let desired = mappings
+        .iter()
+        .map(|Map(src, dst)| Ok((usage(src)?, usage(dst)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(normalize(current) == normalize(&desired))
+}
+
+/// Normalize source and destination usages so that they can be compared.
+///
+/// `hidutil` can return the same source more than once and in any order, so
+/// only the last mapping for each source is kept and the mappings are sorted.
+pub fn normalize(mappings: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut normalized: Vec<(u64, u64)> = Vec::new();
+    for &(src, dst) in mappings.iter().rev() {
+        if !normalized.iter().any(|&(s, _)| s == src) {
+            normalized.push((src, dst));
+        }
+    }
+    normalized.sort_unstable();
+    normalized
+}
+
+/// Whether the error looks like `hidutil` was denied access to the device.
+fn is_permission_error(err: &Error) -> bool {
+    let msg = format!("{:#}", err).to_lowercase();
+    [
+        "not permitted",
+        "permission denied",
+        "privilege",
+        "0xe00002c1",
+    ]
+    .iter()
+    .any(|m| msg.contains(m))
+}
+
+/// Returns the raw source and destination usages currently mapped on the
+/// device.
+pub fn current(hidutil: &Path, device: &Option<Device>) -> Result<Vec<(u64, u64)>> {
+    let mut cmd = command(hidutil);
+    cmd.arg("property");
+    if let Some(d) = device {
+        cmd.arg("--matching").arg(dump_matching_option(d));
+    }
+    let output = cmd.arg("--get").arg("UserKeyMapping").output_text()?;
+    parse_user_key_mapping(&output).context("failed to parse `hidutil property --get` output")
+}
+
+fn parse_user_key_mapping(mut output: &str) -> Result<Vec<(u64, u64)>> {
+    let mut mappings = Vec::new();
+
+    // each mapping is a dictionary of the following form, if there is no
+    // mapping then `hidutil` simply outputs "(null)"
+    //
+    //     {
+    //         HIDKeyboardModifierMappingDst = 30064771300;
+    //         HIDKeyboardModifierMappingSrc = 30064771129;
+    //     }
+    while let Some(start) = output.find('{') {
+        let end = start + output[start..].find('}').context("expected '}'")?;
+        let (mut src, mut dst) = (None, None);
+        for entry in output[start + 1..end].split(';') {
+            let Some((key, value)) = entry.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let value = match value.strip_prefix("0x") {
+                Some(_) => hex::parse(value)?,
+                None => value
+                    .parse()
+                    .with_context(|| format!("failed to parse `{}` as an integer", value))?,
+            };
+            match key.trim() {
+                "HIDKeyboardModifierMappingSrc" => src = Some(value),
+                "HIDKeyboardModifierMappingDst" => dst = Some(value),
+                _ => {}
+            }
+        }
+        mappings.push((
+            src.context("expected 'HIDKeyboardModifierMappingSrc'")?,
+            dst.context("expected 'HIDKeyboardModifierMappingDst'")?,
+        ));
+        output = &output[end + 1..];
+    }
+
+    Ok(mappings)
+}
+
+/// Dump the raw hidutil modification command.
+pub fn dump(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<String> {
+    dump_command(hidutil, device, &dump_set_option(mappings)?, " \\\n  ")
+}
+
+/// Dump the raw hidutil command setting the fn key modifications.
+pub fn dump_apple_fn(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<String> {
+    dump_command(hidutil, device, &dump_apple_fn_option(mappings)?, " \\\n  ")
+}
+
+/// Dump the hidutil modification command as an AppleScript snippet.
+pub fn dump_applescript(
+    hidutil: &Path,
+    device: &Option<Device>,
+    mappings: &[Map],
+) -> Result<String> {
+    let cmd = dump_command(hidutil, device, &dump_set_option(mappings)?, " ")?;
+    let escaped = cmd.replace('\\', "\\\\").replace('"', "\\\"");
+    Ok(format!("do shell script \"{}\"", escaped))
+}
+
+/// Dump the `UserKeyMapping` property as a plist XML document.
+///
+/// The document only contains the property, not the keyboard selection.
+pub fn dump_plist(mappings: &[Map]) -> Result<String> {
+    let mut s = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>UserKeyMapping</key>
+  <array>
+"#,
+    );
+    for Map(src, dst) in mappings {
+        check_pages(src, dst)?;
+        s.push_str("    <dict>\n");
+        writeln!(s, "      <key>HIDKeyboardModifierMappingSrc</key>")?;
+        writeln!(s, "      <integer>{}</integer>", usage(src)?)?;
+        writeln!(s, "      <key>HIDKeyboardModifierMappingDst</key>")?;
+        writeln!(s, "      <integer>{}</integer>", usage(dst)?)?;
+        s.push_str("    </dict>\n");
+    }
+    s.push_str("  </array>\n</dict>\n</plist>");
+    Ok(s)
+}
+
+fn dump_command(hidutil: &Path, device: &Option<Device>, set: &str, sep: &str) -> Result<String> {
+    let mut s = format!("{} property", hidutil.display());
+    if let Some(d) = device.as_ref() {
+        write!(s, "{}--matching '{}'", sep, dump_matching_option(d))?;
+    }
+    write!(s, "{}--set '{}'", sep, set)?;
+    Ok(s)
+}
+
+fn dump_matching_option(device: &Device) -> String {
+    let mut s = format!(
+        "{{\"VendorID\": 0x{:04x}, \"ProductID\": 0x{:04x}",
+        device.vendor_id, device.product_id,
+    );
+    if let Some(serial) = &device.serial {
+        write!(
+            s,
+            ", \"SerialNumber\": {}",
+            serde_json::Value::from(serial.as_str())
+        )
+        .unwrap();
+    }
+    if let Some(location_id) = device.location_id {
+        write!(s, ", \"LocationID\": {:#x}", location_id).unwrap();
+    }
+    if let Some(registry_id) = device.registry_id {
+        write!(s, ", \"RegistryID\": {:#x}", registry_id).unwrap();
+    }
+    if let Some(transport) = &device.transport {
+        write!(
+            s,
+            ", \"Transport\": {}",
+            serde_json::Value::from(transport.as_str())
+        )
+        .unwrap();
+    }
+    s.push('}');
+    s
+}
+
+fn dump_set_option(mappings: &[Map]) -> Result<String> {
+    for Map(src, dst) in mappings {
+        check_pages(src, dst)?;
+    }
+    dump_property_option("UserKeyMapping", mappings)
+}
+
+fn dump_apple_fn_option(mappings: &[Map]) -> Result<String> {
+    for Map(src, dst) in mappings {
+        check_apple_fn_pages(src, dst)?;
+    }
+    dump_property_option("AppleFnUsageMap", mappings)
+}
+
+/// Dump the mappings as the value of the given property.
+fn dump_property_option(property: &str, mappings: &[Map]) -> Result<String> {
+    let mut s = format!("{{\"{}\":[", property);
+    for (i, Map(src, dst)) in mappings.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        s.push('{');
+        write!(
+            s,
+            "\"HIDKeyboardModifierMappingSrc\":0x{:09x},",
+            usage(src)?
+        )?;
+        write!(s, "\"HIDKeyboardModifierMappingDst\":0x{:09x}", usage(dst)?)?;
+        s.push('}');
+    }
+    s.push_str("]}");
+    Ok(s)
+}
+
+/// The usage pages that `hidutil` remaps keys on using `UserKeyMapping`.
+const KEY_MAPPING_PAGES: &[(u64, &str)] = &[
+    (0x07, "Keyboard/Keypad"),
+    (0x0c, "Consumer"),
+    (0xff, "Apple Vendor Top Case"),
+    (0xff01, "Apple Vendor Keyboard"),
+];
+
+/// Check that both keys are on usage pages that `UserKeyMapping` applies to.
+///
+/// Mappings involving any other page are accepted by `hidutil` but silently
+/// ignored.
+fn check_pages(src: &Key, dst: &Key) -> Result<()> {
+    for key in [src, dst] {
+        let page = usage(key)? >> 32;
+        if !KEY_MAPPING_PAGES.iter().any(|&(p, _)| p == page) {
+            let expected: Vec<_> = KEY_MAPPING_PAGES
+                .iter()
+                .map(|(p, name)| format!("{} ({:#x})", name, p))
+                .collect();
+            bail!(
+                "cannot map `{}` to `{}`, `{}` is on usage page {:#x} which `UserKeyMapping` \
+                 ignores, use a key on one of the {} pages instead",
+                src.code(),
+                dst.code(),
+                key.code(),
+                page,
+                expected.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The usage pages that the keys can be mapped to using `AppleFnUsageMap`.
+const APPLE_FN_PAGES: &[(u64, &str)] = &[
+    (0x07, "Keyboard/Keypad"),
+    (0x0c, "Consumer"),
+    (0xff, "Apple Vendor Top Case"),
+];
+
+/// Check that the source is a function key and that the destination is on a
+/// usage page that `AppleFnUsageMap` applies to.
+fn check_apple_fn_pages(src: &Key, dst: &Key) -> Result<()> {
+    if !matches!(src.named(), Key::F(_)) {
+        bail!(
+            "cannot map `{}` to `{}` when pressed with fn, only the function keys F1 to F24 \
+             can be mapped",
+            src.code(),
+            dst.code(),
+        );
+    }
+    let page = usage(dst)? >> 32;
+    if !APPLE_FN_PAGES.iter().any(|&(p, _)| p == page) {
+        let expected: Vec<_> = APPLE_FN_PAGES
+            .iter()
+            .map(|(p, name)| format!("{} ({:#x})", name, p))
+            .collect();
+        bail!(
+            "cannot map `{}` to `{}` when pressed with fn, `{}` is on usage page {:#x}, use a \
+             key on one of the {} pages instead",
+            src.code(),
+            dst.code(),
+            dst.code(),
+            page,
+            expected.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Returns the combined usage page and usage ID for the key.
+pub fn usage(key: &Key) -> Result<u64> {
+    let usage_id = key.usage_id().ok_or_else(|| {
+        anyhow!(
+            "failed to serialize `Key::{:?}`, consider using `Key::Raw(..)`",
+            key
+        )
+    })?;
+    Ok(key.usage_page_id() + usage_id)
+}
+
+fn parse_maybe(s: &str) -> Option<String> {
+    match s {
+        "(null)" => None,
+        _ => Some(s.to_owned()),
+    }
+}
+
+fn split_whitespace_indices(s: &str) -> impl Iterator<Item = (&str, usize)> + '_ {
+    let addr = |s: &str| s.as_ptr() as usize;
+    s.split_whitespace()
+        .map(move |sub| (sub, (addr(sub) - addr(s))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump() {
+        let mappings = vec![Map(Key::Raw(0x7000000e), Key::Raw(0x7000000f))];
+        let device = Device {
+            vendor_id: 0x1234,
+            product_id: 0x5678,
+            name: "test".to_owned(),
+            serial: None,
+            manufacturer: None,
+            status: None,
+            location_id: None,
+            registry_id: None,
+            transport: None,
+        };
+        let output = dump(Path::new("hidutil"), &Some(device), &mappings).unwrap();
+        assert_eq!(
+            output,
+            r#"hidutil property \
+  --matching '{"VendorID": 0x1234, "ProductID": 0x5678}' \
+  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x77000000e,"HIDKeyboardModifierMappingDst":0x77000000f}]}'"#
+        )
+    }
+
+    #[test]
+    fn test_hidutil_path() {
+        let hidutil = Path::new("/opt/bin/hidutil");
+        assert_eq!(command(hidutil).get_program(), "/opt/bin/hidutil");
+        assert_eq!(
+            dump(hidutil, &None, &[]).unwrap(),
+            r#"/opt/bin/hidutil property \
+  --set '{"UserKeyMapping":[]}'"#
+        );
+    }
+
+    #[test]
+    fn test_dump_consumer() {
+        let mappings = [Map(Key::F(1), Key::VolumeUp), Map(Key::PlayPause, Key::Fn)];
+        assert_eq!(
+            dump(Path::new("hidutil"), &None, &mappings).unwrap(),
+            r#"hidutil property \
+  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x70000003a,"HIDKeyboardModifierMappingDst":0xc000000e9},{"HIDKeyboardModifierMappingSrc":0xc000000cd,"HIDKeyboardModifierMappingDst":0xff00000003}]}'"#
+        );
+    }
+
+    #[test]
+    fn test_dump_fn_row_media() {
+        // the F-keys are on the keyboard page and the media keys on the
+        // consumer page
+        let mappings = [
+            Map(Key::F(1), Key::BrightnessDown),
+            Map(Key::F(7), Key::PrevTrack),
+        ];
+        assert_eq!(
+            dump(Path::new("hidutil"), &None, &mappings).unwrap(),
+            r#"hidutil property \
+  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x70000003a,"HIDKeyboardModifierMappingDst":0xc00000070},{"HIDKeyboardModifierMappingSrc":0x700000040,"HIDKeyboardModifierMappingDst":0xc000000b6}]}'"#
+        );
+    }
+
+    #[test]
+    fn test_dump_apple_fn() {
+        let device = Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad");
+        let mappings = [Map(Key::F(1), Key::VolumeUp), Map(Key::F(2), Key::F(12))];
+        assert_eq!(
+            dump_apple_fn(Path::new("hidutil"), &Some(device), &mappings).unwrap(),
+            r#"hidutil property \
+  --matching '{"VendorID": 0x05ac, "ProductID": 0x0342}' \
+  --set '{"AppleFnUsageMap":[{"HIDKeyboardModifierMappingSrc":0x70000003a,"HIDKeyboardModifierMappingDst":0xc000000e9},{"HIDKeyboardModifierMappingSrc":0x70000003b,"HIDKeyboardModifierMappingDst":0x700000045}]}'"#
+        );
+
+        let err = dump_apple_fn(
+            Path::new("hidutil"),
+            &None,
+            &[Map(Key::CapsLock, Key::Mute)],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot map `capslock` to `mute` when pressed with fn, only the function keys F1 to \
+             F24 can be mapped"
+        );
+        // Generic Desktop System Sleep
+        let mappings = [Map(Key::F(1), Key::Raw(0x1_0000_0082))];
+        let err = dump_apple_fn(Path::new("hidutil"), &None, &mappings).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot map `f1` to `0x100000082` when pressed with fn, `0x100000082` is on usage \
+             page 0x1, use a key on one of the Keyboard/Keypad (0x7), Consumer (0xc), Apple Vendor Top Case \
+             (0xff) pages instead"
+        );
+    }
+
+    #[test]
+    fn test_dump_unsupported_page() {
+        // Generic Desktop System Sleep
+        let mappings = [Map(Key::F(1), Key::Raw(0x1_0000_0082))];
+        assert_eq!(
+            dump(Path::new("hidutil"), &None, &mappings)
+                .unwrap_err()
+                .to_string(),
+            "cannot map `f1` to `0x100000082`, `0x100000082` is on usage page 0x1 which \
+             `UserKeyMapping` ignores, use a key on one of the Keyboard/Keypad (0x7), Consumer (0xc), \
+             Apple Vendor Top Case (0xff), Apple Vendor Keyboard (0xff01) pages instead"
+        );
+    }
+
+    #[test]
+    fn test_dump_out_of_range_key() {
+        let mappings = [Map(Key::Lang(10), Key::Escape)];
+        assert_eq!(
+            dump(Path::new("hidutil"), &None, &mappings)
+                .unwrap_err()
+                .to_string(),
+            "failed to serialize `Key::Lang(10)`, consider using `Key::Raw(..)`"
+        );
+    }
+
+    #[test]
+    fn test_dump_applescript() {
+        let device = Device::new(0x1234, 0x5678, "test");
+        let mappings = [Map(Key::CapsLock, Key::Escape)];
+        assert_eq!(
+            dump_applescript(Path::new("hidutil"), &Some(device), &mappings).unwrap(),
+            r#"do shell script "hidutil property --matching '{\"VendorID\": 0x1234, \"ProductID\": 0x5678}' --set '{\"UserKeyMapping\":[{\"HIDKeyboardModifierMappingSrc\":0x700000039,\"HIDKeyboardModifierMappingDst\":0x700000029}]}'""#
+        );
+        let device = Device::new(0x1234, 0x5678, "test").with_serial(Some(r"a\b".to_owned()));
+        assert!(dump_applescript(Path::new("hidutil"), &Some(device), &[])
+            .unwrap()
+            .contains(r#"\"SerialNumber\": \"a\\\\b\""#));
+    }
+
+    #[test]
+    fn test_dump_plist() {
+        let mappings = [Map(Key::CapsLock, Key::Escape)];
+        assert_eq!(
+            dump_plist(&mappings).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>UserKeyMapping</key>
+  <array>
+    <dict>
+      <key>HIDKeyboardModifierMappingSrc</key>
+      <integer>30064771129</integer>
+      <key>HIDKeyboardModifierMappingDst</key>
+      <integer>30064771113</integer>
+    </dict>
+  </array>
+</dict>
+</plist>"#
+        );
+        let value: plist::Value =
+            plist::from_bytes(dump_plist(&mappings).unwrap().as_bytes()).unwrap();
+        let mapping = &value.as_dictionary().unwrap()["UserKeyMapping"]
+            .as_array()
+            .unwrap()[0];
+        assert_eq!(
+            mapping.as_dictionary().unwrap()["HIDKeyboardModifierMappingSrc"].as_unsigned_integer(),
+            Some(0x700000039)
+        );
+    }
+
+    #[test]
+    fn test_device_new() {
+        let device = Device::new(0x1234, 0x5678, "test");
+        assert_eq!(
+            device,
+            Device {
+                vendor_id: 0x1234,
+                product_id: 0x5678,
+                name: "test".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
+            }
+        );
+        let mappings = [Map(Key::CapsLock, Key::Escape)];
+        assert_eq!(
+            dump(Path::new("hidutil"), &Some(device), &mappings).unwrap(),
+            r#"hidutil property \
+  --matching '{"VendorID": 0x1234, "ProductID": 0x5678}' \
+  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x700000039,"HIDKeyboardModifierMappingDst":0x700000029}]}'"#
+        );
+    }
+
+    #[test]
+    fn test_is_applied() {
+        let current = [(0x7000000e4, 0x700000039), (0x700000039, 0x7000000e4)];
+        let mappings = [
+            Map(Key::CapsLock, Key::RightControl),
+            Map(Key::RightControl, Key::CapsLock),
+        ];
+        assert!(is_applied(&current, &mappings).unwrap());
+        assert!(!is_applied(&current, &mappings[..1]).unwrap());
+        assert!(!is_applied(&current[..1], &mappings).unwrap());
+        assert!(!is_applied(&current, &[Map(Key::CapsLock, Key::Escape)]).unwrap());
+        assert!(is_applied(&[], &[]).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_if_changed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("kb-remap-test-apply-if-changed");
+        fs::create_dir_all(&dir).unwrap();
+        let hidutil = dir.join("hidutil");
+        let log = dir.join("log");
+        // a fake `hidutil` that logs its arguments and maps capslock to escape
+        fs::write(
+            &hidutil,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> '{}'\ncase \"$*\" in *--get*) echo '(
+    {{
+        HIDKeyboardModifierMappingDst = 30064771113;
+        HIDKeyboardModifierMappingSrc = 30064771129;
+    }}
+)' ;; esac\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&hidutil, fs::Permissions::from_mode(0o755)).unwrap();
+        let run = |device: &Option<Device>, mappings: &[Map]| {
+            let _ = fs::remove_file(&log);
+            let applied = apply_if_changed(&hidutil, device, mappings).unwrap();
+            (applied, fs::read_to_string(&log).unwrap())
+        };
+        let device = Some(Device::new(0xc45, 0x7692, "USB Keyboard"));
+
+        let (applied, log) = run(&device, &[Map(Key::CapsLock, Key::Escape)]);
+        assert!(!applied);
+        assert!(log.contains("--get") && !log.contains("--set"), "{}", log);
+
+        let (applied, log) = run(&device, &[Map(Key::CapsLock, Key::LeftControl)]);
+        assert!(applied);
+        assert!(log.contains("--set"), "{}", log);
+
+        // every device can't be compared
+        let (applied, log) = run(&None, &[Map(Key::CapsLock, Key::Escape)]);
+        assert!(applied);
+        assert!(log.contains("--set"), "{}", log);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_permission_error() {
+        let err = anyhow!(
+            "subprocess didn't exit successfully `\"hidutil\" \"property\"` (exit status: 1)\n\
+             --- stderr\nIOHIDEventSystemClientSetProperty failed: (iokit/common) not permitted"
+        );
+        assert!(is_permission_error(&err));
+        let err = anyhow!("subprocess didn't exit successfully").context("something else");
+        assert!(!is_permission_error(&err));
+    }
+
+    #[test]
+    fn test_parse_user_key_mapping() {
+        let output = r#"(
+        {
+        HIDKeyboardModifierMappingDst = 30064771300;
+        HIDKeyboardModifierMappingSrc = 30064771129;
+    },
+        {
+        HIDKeyboardModifierMappingDst = 30064771129;
+        HIDKeyboardModifierMappingSrc = 30064771300;
+    }
+)
+"#;
+        let mappings = parse_user_key_mapping(output).unwrap();
+        assert_eq!(
+            mappings,
+            vec![(0x700000039, 0x7000000e4), (0x7000000e4, 0x700000039)]
+        );
+    }
+
+    #[test]
+    fn test_parse_user_key_mapping_null() {
+        let mappings = parse_user_key_mapping("(null)\n").unwrap();
+        assert_eq!(mappings, Vec::new());
+    }
+
+    fn row(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|&(h, v)| (h.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_columns_basic() {
+        let rows = parse_columns("VendorID ProductID Product", "0x0      0x0       BTM\n");
+        assert_eq!(
+            rows,
+            [row(&[
+                ("VendorID", "0x0"),
+                ("ProductID", "0x0"),
+                ("Product", "BTM")
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_parse_columns_value_wider_than_header() {
+        let rows = parse_columns(
+            "VendorID ProductID Product Built-In",
+            "0x0      0x0       TouchBarUserDevice  1\n",
+        );
+        // the value is split at the header index of the next column
+        assert_eq!(
+            rows,
+            [row(&[
+                ("VendorID", "0x0"),
+                ("ProductID", "0x0"),
+                ("Product", "TouchBar"),
+                ("Built-In", "UserDevice  1"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_parse_columns_trailing_column_with_spaces() {
+        let rows = parse_columns(
+            "VendorID Product",
+            "0x5ac    Apple Internal Keyboard / Trackpad   \n",
+        );
+        assert_eq!(
+            rows,
+            [row(&[
+                ("VendorID", "0x5ac"),
+                ("Product", "Apple Internal Keyboard / Trackpad")
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_parse_columns_wrapped_line() {
+        let rows = parse_columns(
+            "VendorID ProductID Product             Built-In",
+            "0x5ac    0x8600    TouchBar\nUserDevice    1\n0x6ac    0x9600    Made Up             1\n",
+        );
+        assert_eq!(
+            rows,
+            [
+                row(&[
+                    ("VendorID", "0x5ac"),
+                    ("ProductID", "0x8600"),
+                    ("Product", "TouchBar\nUserDevice"),
+                    ("Built-In", "1"),
+                ]),
+                row(&[
+                    ("VendorID", "0x6ac"),
+                    ("ProductID", "0x9600"),
+                    ("Product", "Made Up"),
+                    ("Built-In", "1"),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_columns_no_trailing_newline() {
+        let rows = parse_columns("VendorID Product", "0x0      BTM");
+        assert_eq!(rows, [row(&[("VendorID", "0x0"), ("Product", "BTM")])]);
+    }
+
+    #[test]
+    fn test_parse_columns_short_last_line() {
+        let rows = parse_columns("VendorID ProductID Product", "0x0");
+        assert_eq!(
+            rows,
+            [row(&[
+                ("VendorID", "0x0"),
+                ("ProductID", ""),
+                ("Product", "")
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_parse_columns_blank_lines() {
+        let rows = parse_columns("VendorID Product", "\n\n0x0      BTM\n\n\n");
+        assert_eq!(rows, [row(&[("VendorID", "0x0"), ("Product", "BTM")])]);
+    }
 
-    // first find the header and skip past it
-    const HEADER: &str = "Devices:\n";
-    let start = output.find(HEADER).context("expected 'Devices:'")? + HEADER.len();
-    output = &output[start..];
-
-    // then parse the indices of the header
-    let line = output
-        .find('\n')
-        .map(|i| &output[..i])
-        .context("expected header")?;
-    let indices: Vec<_> = split_whitespace_indices(line)
-        .map(|(header, i)| Some((header.trim(), i)))
-        .chain([None])
-        .collect();
+    #[test]
+    fn test_parse_columns_empty_header() {
+        assert!(parse_columns("", "0x0      BTM\n").is_empty());
+        assert!(parse_columns("   ", "0x0      BTM\n").is_empty());
+    }
 
-    // now skip over the header
-    output = &output[line.len() + 1..];
+    #[test]
+    fn test_parse_hidutil_output_empty_header() {
+        let devices = parse_hidutil_output("Devices:\n\n0x0 0x0 BTM\n").unwrap();
+        assert_eq!(devices, Vec::new());
+    }
 
-    while !output.is_empty() {
-        // skip over any leading newlines
-        if output.starts_with('\n') {
-            output = &output[1..];
-            continue;
-        }
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("0x5ac").unwrap(), 0x5ac);
+        assert_eq!(parse_id("1452").unwrap(), 0x5ac);
+        assert_eq!(
+            parse_id("5ac").unwrap_err().to_string(),
+            "failed to parse `5ac` as hexadecimal or decimal"
+        );
+    }
 
-        let mut line_end = 0;
+    #[test]
+    fn test_parse_id_sections() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Class
+0x05ac   0x0342    0x0        0x1       0x6   0x100000a1b SPI       AppleUserHIDEventService
 
-        // parse the line into a map of header -> value using the header
-        // indices to know where columns start and end, for the last column
-        // we simply find the next newline
-        let map: HashMap<_, _> = indices
-            .windows(2)
-            .map(|w| match *w {
-                [Some((header, m)), Some((_, n))] => {
-                    let value = output[m..n].trim();
-                    (header, value)
-                }
-                [Some((header, m)), None] => {
-                    line_end = output[m..]
-                        .find('\n')
-                        .map(|i| m + i + 1)
-                        .unwrap_or(output.len());
-                    let value = output[m..line_end].trim();
-                    (header, value)
-                }
-                _ => unreachable!(),
-            })
-            .collect();
+Devices:
+VendorID ProductID Product
+0x5AC    0X342     Apple Keyboard
+"#;
+        let ids = |header| {
+            let (header, lines) = find_section(output, header)
+                .unwrap()
+                .split_once('\n')
+                .unwrap();
+            let row = parse_columns(header, lines).remove(0);
+            (
+                parse_id(&row["VendorID"]).unwrap(),
+                parse_id(&row["ProductID"]).unwrap(),
+            )
+        };
+        assert_eq!(ids("Services:\n"), (0x5ac, 0x342));
+        assert_eq!(ids("Services:\n"), ids("Devices:\n"));
+    }
 
-        output = &output[line_end..];
+    #[test]
+    fn test_parse_hidutil_output_decimal_id() {
+        let output = r#"Devices:
+VendorID ProductID Product
+1452     0x342     Apple Keyboard
+0xc45    0x7692    USB Keyboard
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device::new(0x5ac, 0x342, "Apple Keyboard"),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ]
+        );
+        assert_eq!(decimal_ids(output), ["1452"]);
+        assert!(decimal_ids("Devices:\nVendorID ProductID Product\n0x5ac 0x342 A\n").is_empty());
+    }
 
-        let name = match parse_maybe(map["Product"]) {
-            Some(name) => name.replace('\n', " "),
-            None => continue,
-        };
-        let vendor_id = hex::parse(map["VendorID"])?;
-        let product_id = hex::parse(map["ProductID"])?;
+    #[test]
+    fn test_parse_hidutil_output_serial() {
+        let output = r#"Devices:
+VendorID ProductID Product          SerialNumber      Built-In
+0x5ac    0x29c     Magic Keyboard   a8-91-3d-0e-6f-21 (null)
+0xc45    0x7692    USB Keyboard     (null)            (null)
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device::new(0x5ac, 0x29c, "Magic Keyboard")
+                    .with_serial(Some("a8-91-3d-0e-6f-21".to_owned())),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ]
+        );
+    }
 
-        devices.push(Device {
-            vendor_id,
-            product_id,
-            name,
-        });
+    #[test]
+    fn test_parse_hidutil_output_manufacturer() {
+        let output = r#"Devices:
+VendorID ProductID Product          Manufacturer Built-In
+0x5ac    0x29c     Magic Keyboard   Apple Inc.   (null)
+0xc45    0x7692    USB Keyboard     (null)       (null)
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device::new(0x5ac, 0x29c, "Magic Keyboard")
+                    .with_manufacturer(Some("Apple Inc.".to_owned())),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ]
+        );
     }
 
-    devices.sort();
-    devices.dedup();
+    #[test]
+    fn test_status() {
+        let services = [(0x5ac, 0x342), (0xc45, 0x7692)];
+        assert_eq!(
+            status(
+                &Device::new(0x5ac, 0x342, "Apple Internal Keyboard"),
+                &services
+            ),
+            Status::Connected
+        );
+        assert_eq!(
+            status(&Device::new(0x5ac, 0x29c, "Magic Keyboard"), &services),
+            Status::Remembered
+        );
+        assert_eq!(
+            status(&Device::new(0x5ac, 0x29c, "Magic Keyboard"), &[]),
+            Status::Remembered
+        );
+    }
 
-    Ok(devices)
-}
+    #[test]
+    fn test_parse_hidutil_output_status() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport
+0x5ac    0x342     0x0        1         6     0x100000a4c SPI
 
-/// Apply the modifications to the device.
-pub fn apply(device: &Option<Device>, mappings: &[Map]) -> Result<()> {
-    let mut cmd = process::Command::new("hidutil");
-    cmd.arg("property");
-    if let Some(d) = device {
-        cmd.arg("--matching").arg(dump_matching_option(d));
+Devices:
+VendorID ProductID Product                 Built-In
+0x5ac    0x342     Apple Internal Keyboard 1
+0x5ac    0x29c     Magic Keyboard          (null)
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device::new(0x5ac, 0x29c, "Magic Keyboard").with_status(Some(Status::Remembered)),
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard")
+                    .with_status(Some(Status::Connected))
+                    .with_location_id(Some(0x0))
+                    .with_transport(Some("SPI".to_owned())),
+            ]
+        );
     }
-    cmd.arg("--set")
-        .arg(dump_set_option(mappings)?)
-        .output_text()?;
-    Ok(())
-}
 
-/// Dump the raw hidutil modification command.
-pub fn dump(device: &Option<Device>, mappings: &[Map]) -> Result<String> {
-    let mut s = String::from("hidutil property");
-    if let Some(d) = device.as_ref() {
-        write!(s, " \\\n  --matching '{}'", dump_matching_option(d))?;
+    #[test]
+    fn test_parse_hidutil_output_transports() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Product     Built-In
+0x5ac    0x24f     0x14100000 0x1       0x6   0x100000b2c USB       Keychron K2 (null)
+0x5ac    0x24f     0x3c9f1a2b 0x1       0x6   0x100000d4f Bluetooth Keychron K2 (null)
+
+Devices:
+VendorID ProductID Product     Built-In
+0x5ac    0x24f     Keychron K2 (null)
+"#;
+        let device = Device::new(0x5ac, 0x24f, "Keychron K2").with_status(Some(Status::Connected));
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                device
+                    .clone()
+                    .with_location_id(Some(0x14100000))
+                    .with_transport(Some("USB".to_owned())),
+                device
+                    .with_location_id(Some(0x3c9f1a2b))
+                    .with_transport(Some("Bluetooth".to_owned())),
+            ]
+        );
+        assert_eq!(
+            dump_matching_option(&devices[1]),
+            r#"{"VendorID": 0x05ac, "ProductID": 0x024f, "LocationID": 0x3c9f1a2b, "Transport": "Bluetooth"}"#
+        );
     }
-    write!(s, " \\\n  --set '{}'", dump_set_option(mappings)?)?;
-    Ok(s)
-}
 
-fn dump_matching_option(device: &Device) -> String {
-    format!(
-        "{{\"VendorID\": 0x{:04x}, \"ProductID\": 0x{:04x}}}",
-        device.vendor_id, device.product_id,
-    )
-}
+    #[test]
+    fn test_parse_hidutil_output_identical() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Product      Built-In
+0xc45    0x7692    0x14100000 0x1       0x6   0x100000b2c USB       USB Keyboard (null)
+0xc45    0x7692    0x14100000 0xc       0x1   0x100000b2d USB       USB Keyboard (null)
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000c3e USB       USB Keyboard (null)
 
-fn dump_set_option(mappings: &[Map]) -> Result<String> {
-    let mut s = String::from("{\"UserKeyMapping\":[");
-    for (i, Map(src, dst)) in mappings.iter().enumerate() {
-        let err = |&key| {
-            anyhow!(
-                "failed to serialize `Key::{:?}`, consider using `Key::Raw(..)`",
-                key
-            )
-        };
-        if i > 0 {
-            s.push(',');
-        }
-        s.push('{');
-        let src = src.usage_page_id() + src.usage_id().ok_or_else(|| err(src))?;
-        write!(s, "\"HIDKeyboardModifierMappingSrc\":0x{:09x},", src,)?;
-        let dst = dst.usage_page_id() + dst.usage_id().ok_or_else(|| err(dst))?;
-        write!(s, "\"HIDKeyboardModifierMappingDst\":0x{:09x}", dst)?;
-        s.push('}');
+Devices:
+VendorID ProductID Product      Built-In
+0xc45    0x7692    USB Keyboard (null)
+0xc45    0x7692    USB Keyboard (null)
+"#;
+        let device = Device::new(0xc45, 0x7692, "USB Keyboard")
+            .with_status(Some(Status::Connected))
+            .with_transport(Some("USB".to_owned()));
+        assert_eq!(
+            parse_hidutil_output(output).unwrap(),
+            vec![
+                device.clone().with_location_id(Some(0x14100000)),
+                device.with_location_id(Some(0x14200000)),
+            ]
+        );
+        assert_eq!(
+            find_service(output, 0x100000c3e).unwrap(),
+            Device::new(0xc45, 0x7692, "USB Keyboard").with_registry_id(Some(0x100000c3e))
+        );
+        assert_eq!(
+            find_service(output, 0x1).unwrap_err().to_string(),
+            "failed to find service with registry ID `0x1`"
+        );
     }
-    s.push_str("]}");
-    Ok(s)
-}
 
-fn parse_maybe(s: &str) -> Option<String> {
-    match s {
-        "(null)" => None,
-        _ => Some(s.to_owned()),
+    #[test]
+    fn test_dump_location_and_registry_id() {
+        let device = Device::new(0xc45, 0x7692, "USB Keyboard")
+            .with_location_id(Some(0x14200000))
+            .with_registry_id(Some(0x100000c3e));
+        assert_eq!(
+            dump(Path::new("hidutil"), &Some(device), &[]).unwrap(),
+            r#"hidutil property \
+  --matching '{"VendorID": 0x0c45, "ProductID": 0x7692, "LocationID": 0x14200000, "RegistryID": 0x100000c3e}' \
+  --set '{"UserKeyMapping":[]}'"#
+        );
     }
-}
 
-fn split_whitespace_indices(s: &str) -> impl Iterator<Item = (&str, usize)> + '_ {
-    let addr = |s: &str| s.as_ptr() as usize;
-    s.split_whitespace()
-        .map(move |sub| (sub, (addr(sub) - addr(s))))
-}
+    #[test]
+    fn test_normalize() {
+        let current = [
+            (0x700000039, 0x700000029),
+            (0x700000004, 0x700000005),
+            (0x700000039, 0x7000000e0),
+        ];
+        assert_eq!(
+            normalize(&current),
+            [(0x700000004, 0x700000005), (0x700000039, 0x7000000e0)]
+        );
+        assert!(is_applied(
+            &current,
+            &[
+                Map(Key::CapsLock, Key::LeftControl),
+                Map(Key::Char('a'), Key::Char('b')),
+            ]
+        )
+        .unwrap());
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_dump_serial() {
+        let device = Device::new(0x5ac, 0x29c, "Magic Keyboard")
+            .with_serial(Some("a8-91-3d-0e-6f-21".to_owned()));
+        assert_eq!(
+            dump(Path::new("hidutil"), &Some(device), &[]).unwrap(),
+            r#"hidutil property \
+  --matching '{"VendorID": 0x05ac, "ProductID": 0x029c, "SerialNumber": "a8-91-3d-0e-6f-21"}' \
+  --set '{"UserKeyMapping":[]}'"#
+        );
+    }
 
-    use crate::types::{Key, Map};
+    #[test]
+    fn test_parse_fields() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Product        Built-In
+0x5ac    0x342     0x0        0x1       0x6   0x100000a1b SPI       Apple Keyboard 1
+0x5ac    0x342     0x0        0xff00    0x3   0x100000a1c SPI       Apple Keyboard 1
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000b2c USB       USB Keyboard   (null)
+"#;
+        let device = Device::new(0xc45, 0x7692, "USB Keyboard");
+        let fields = parse_fields(output, &device);
+        let expected: Vec<_> = [
+            ("VendorID", "0xc45"),
+            ("ProductID", "0x7692"),
+            ("LocationID", "0x14200000"),
+            ("UsagePage", "0x1"),
+            ("Usage", "0x6"),
+            ("RegistryID", "0x100000b2c"),
+            ("Transport", "USB"),
+            ("Product", "USB Keyboard"),
+            ("Built-In", "(null)"),
+        ]
+        .iter()
+        .map(|&(h, v)| (h.to_owned(), v.to_owned()))
+        .collect();
+        assert_eq!(fields, [expected]);
+
+        let device = Device::new(0x5ac, 0x342, "");
+        assert_eq!(parse_fields(output, &device).len(), 2);
+
+        let device = Device::new(0x5ac, 0x342, "Other Keyboard");
+        assert!(parse_fields(output, &device).is_empty());
+    }
 
     #[test]
-    fn test_dump() {
-        let mappings = vec![Map(Key::Raw(0x7000000e), Key::Raw(0x7000000f))];
-        let device = Device {
-            vendor_id: 0x1234,
-            product_id: 0x5678,
-            name: "test".to_owned(),
+    fn test_parse_fields_both_sections() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Class
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000b2c USB       AppleUserHIDEventService
+0xc45    0x7692    0x14200000 0xc       0x1   0x100000b2d USB       AppleUserHIDEventService
+0x5ac    0x342     0x0        0x1       0x6   0x100000a1b SPI       AppleUserHIDEventService
+
+Devices:
+VendorID ProductID Product      Built-In
+0xc45    0x7692    USB Keyboard (null)
+0x5ac    0x342     Apple Keyboard 1
+"#;
+        let device = Device::new(0xc45, 0x7692, "USB Keyboard");
+        let fields = parse_fields(output, &device);
+        let row = |usage_page, usage, registry_id| -> Vec<(String, String)> {
+            [
+                ("VendorID", "0xc45"),
+                ("ProductID", "0x7692"),
+                ("Product", "USB Keyboard"),
+                ("Built-In", "(null)"),
+                ("LocationID", "0x14200000"),
+                ("UsagePage", usage_page),
+                ("Usage", usage),
+                ("RegistryID", registry_id),
+                ("Transport", "USB"),
+                ("Class", "AppleUserHIDEventService"),
+            ]
+            .iter()
+            .map(|&(h, v)| (h.to_owned(), v.to_owned()))
+            .collect()
         };
-        let output = dump(&Some(device), &mappings).unwrap();
         assert_eq!(
-            output,
-            r#"hidutil property \
-  --matching '{"VendorID": 0x1234, "ProductID": 0x5678}' \
-  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x77000000e,"HIDKeyboardModifierMappingDst":0x77000000f}]}'"#
-        )
+            fields,
+            [
+                row("0x1", "0x6", "0x100000b2c"),
+                row("0xc", "0x1", "0x100000b2d")
+            ]
+        );
+
+        // a selected location only shows the services at that location
+        let device = device.with_location_id(Some(0x14100000));
+        assert_eq!(
+            parse_fields(output, &device),
+            [row("0x1", "0x6", "0x100000b2c")[..4].to_vec()]
+        );
     }
 
     #[test]
@@ -192,6 +1747,12 @@ VendorID ProductID Product
 "#;
         let devices = parse_hidutil_output(output).unwrap();
         assert_eq!(devices, Vec::new());
+        assert!(has_devices(output));
+
+        // without any section the parser returns no devices without warning
+        let output = "Nothing to see here\n";
+        assert_eq!(parse_hidutil_output(output).unwrap(), Vec::new());
+        assert!(!has_devices(output));
     }
 
     #[test]
@@ -207,6 +1768,55 @@ VendorID ProductID Product
         assert_eq!(devices, Vec::new());
     }
 
+    #[test]
+    fn test_parse_hidutil_output_no_devices_section() {
+        let output = r#"
+Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Class
+0x5ac    0x342     0x0        0x1       0x6   0x100000a1b SPI       AppleUserHIDEventService
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(devices, Vec::new());
+    }
+
+    #[test]
+    fn test_parse_hidutil_output_services_fallback() {
+        let output = r#"Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Product        Built-In
+0x5ac    0x342     0x0        0x1       0x6   0x100000a1b SPI       Apple Keyboard 1
+0x5ac    0x342     0x0        0xff00    0x3   0x100000a1c SPI       Apple Keyboard 1
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000b2c USB       USB Keyboard   (null)
+"#;
+        let devices = parse_hidutil_output(output).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                Device {
+                    vendor_id: 0x5ac,
+                    product_id: 0x342,
+                    name: "Apple Keyboard".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: Some(0x0),
+                    registry_id: None,
+                    transport: Some("SPI".to_owned()),
+                },
+                Device {
+                    vendor_id: 0xc45,
+                    product_id: 0x7692,
+                    name: "USB Keyboard".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: Some(0x14200000),
+                    registry_id: None,
+                    transport: Some("USB".to_owned()),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_hidutil_output_basic() {
         let output = r#"Devices:
@@ -219,7 +1829,13 @@ VendorID ProductID Product Built-In
             vec![Device {
                 vendor_id: 0,
                 product_id: 0,
-                name: "BTM".to_owned()
+                name: "BTM".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
             },]
         );
     }
@@ -235,7 +1851,13 @@ VendorID ProductID Product Built-In
             vec![Device {
                 vendor_id: 0,
                 product_id: 0,
-                name: "BTM".to_owned()
+                name: "BTM".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
             },]
         );
     }
@@ -263,12 +1885,24 @@ VendorID ProductID Product             Built-In
                 Device {
                     vendor_id: 0,
                     product_id: 0,
-                    name: "BTM".to_owned()
+                    name: "BTM".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: None,
+                    registry_id: None,
+                    transport: None,
                 },
                 Device {
                     vendor_id: 0x5ac,
                     product_id: 0x8600,
-                    name: "TouchBarUserDevice".to_owned()
+                    name: "TouchBarUserDevice".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: None,
+                    registry_id: None,
+                    transport: None,
                 }
             ]
         );
@@ -290,17 +1924,35 @@ UserDevice    1
                 Device {
                     vendor_id: 0,
                     product_id: 0,
-                    name: "BTM".to_owned()
+                    name: "BTM".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: None,
+                    registry_id: None,
+                    transport: None,
                 },
                 Device {
                     vendor_id: 0x5ac,
                     product_id: 0x8600,
-                    name: "TouchBar UserDevice".to_owned()
+                    name: "TouchBar UserDevice".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: None,
+                    registry_id: None,
+                    transport: None,
                 },
                 Device {
                     vendor_id: 0x6ac,
                     product_id: 0x9600,
                     name: "Made Up".to_owned(),
+                    serial: None,
+                    manufacturer: None,
+                    status: None,
+                    location_id: None,
+                    registry_id: None,
+                    transport: None,
                 }
             ]
         );
@@ -327,8 +1979,95 @@ VendorID ProductID Product             Built-In
             vec![Device {
                 vendor_id: 0,
                 product_id: 0,
-                name: "BTM".to_owned()
+                name: "BTM".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
             }]
         );
     }
+
+    #[test]
+    fn test_group_by_id() {
+        let devices = vec![
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0x5ac, 0x342, "Apple Internal Touch Bar"),
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+        ];
+        assert_eq!(devices[0].id_key(), devices[1].id_key());
+        assert_eq!(
+            group_by_id(&devices),
+            vec![
+                Device::new(
+                    0x5ac,
+                    0x342,
+                    "Apple Internal Keyboard / Trackpad, Apple Internal Touch Bar"
+                ),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_cache() {
+        let path = std::env::temp_dir().join("kb-remap-test-list-cache.txt");
+        fs::write(&path, "Devices:\n").unwrap();
+        let now = SystemTime::now();
+        let ttl = Duration::from_secs(5);
+        assert_eq!(read_cache(&path, ttl, now).as_deref(), Some("Devices:\n"));
+        assert_eq!(read_cache(&path, ttl, now + Duration::from_secs(10)), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_cache(&path, ttl, now), None);
+    }
+
+    #[test]
+    fn test_list_cache_path() {
+        let path = list_cache_path(Path::new("hidutil"));
+        assert_eq!(path, list_cache_path(Path::new("hidutil")));
+        assert_ne!(path, list_cache_path(Path::new("/tmp/fake/hidutil")));
+        assert!(path.starts_with(cache_dir()));
+    }
+
+    #[test]
+    fn test_clear_cache() {
+        let dir = std::env::temp_dir().join("kb-remap-test-clear-cache");
+        let _ = fs::remove_dir_all(&dir);
+        assert!(clear_cache(&dir).unwrap().is_empty());
+
+        fs::create_dir_all(dir.join("subdir")).unwrap();
+        let files = [
+            dir.join("hidutil-list-a.txt"),
+            dir.join("hidutil-list-b.txt"),
+        ];
+        for path in &files {
+            fs::write(path, "Devices:\n").unwrap();
+        }
+        assert_eq!(cache_files(&dir).unwrap(), files);
+        assert_eq!(clear_cache(&dir).unwrap(), files);
+        let result = (cache_files(&dir).unwrap(), dir.join("subdir").exists());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.0.is_empty());
+        // only files are removed
+        assert!(result.1);
+    }
+
+    #[test]
+    fn test_group_by_vendor() {
+        let devices = vec![
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0xc45, 0x7693, "USB Keyboard"),
+            Device::new(0x5ac, 0x29c, "Magic Keyboard"),
+        ];
+        assert_eq!(
+            group_by_vendor(&devices),
+            vec![
+                (0x5ac, vec![devices[1].clone(), devices[3].clone()]),
+                (0xc45, vec![devices[0].clone(), devices[2].clone()]),
+            ]
+        );
+    }
 }
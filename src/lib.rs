@@ -0,0 +1,108 @@
+//! Parse the keyboards listed by the macOS `hidutil` program and modify the
+//! keys they produce.
+//!
+//! This is the library behind the `kb-remap` binary. For example, to parse
+//! captured `hidutil list` output without running `hidutil`:
+//!
+//! The functions at the top level return a typed [`Error`] so that callers can
+//! tell, for example, a missing keyboard apart from `hidutil` failing.
+//!
+//! ```
+//! let output = "\
+//! Devices:
+//! VendorID ProductID Product
+//! 0xc45    0x7692    USB Keyboard
+//! ";
+//! let devices = kb_remap::parse_hidutil_output(output).unwrap();
+//! assert_eq!(devices[0].name, "USB Keyboard");
+//! ```
+
+mod cmd;
+mod error;
+pub mod hex;
+pub mod hid;
+pub mod types;
+
+use std::path::Path;
+
+pub use crate::error::Error;
+pub use crate::hid::Device;
+use crate::types::Map;
+
+/// Returns the keyboards listed by `hidutil`.
+pub fn list(hidutil: &Path) -> Result<Vec<Device>, Error> {
+    let output = hid::list_raw(hidutil).map_err(|e| Error::from_anyhow(e, Error::CommandFailed))?;
+    parse_hidutil_output(&output)
+}
+
+/// Parse the keyboards from captured `hidutil list` output.
+pub fn parse_hidutil_output(output: &str) -> Result<Vec<Device>, Error> {
+    hid::parse_hidutil_output(output).map_err(|e| Error::from_anyhow(e, Error::ParseFailed))
+}
+
+/// Returns the keyboard with the given name.
+///
+/// Fails with [`Error::NoMatch`] if there is none and with
+/// [`Error::MultipleMatches`] if more than one keyboard has the name.
+pub fn find(devices: &[Device], name: &str) -> Result<Device, Error> {
+    let mut matches: Vec<_> = devices.iter().filter(|d| d.name == name).cloned().collect();
+    match matches.len() {
+        0 => Err(Error::NoMatch(name.to_owned())),
+        1 => Ok(matches.remove(0)),
+        _ => Err(Error::MultipleMatches(matches)),
+    }
+}
+
+/// Apply the mappings to the keyboard, or to every keyboard if it is `None`.
+pub fn apply(hidutil: &Path, device: &Option<Device>, mappings: &[Map]) -> Result<(), Error> {
+    hid::apply(hidutil, device, mappings).map_err(|e| Error::from_anyhow(e, Error::CommandFailed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+            Device::new(0xc45, 0x7693, "USB Keyboard"),
+        ]
+    }
+
+    #[test]
+    fn find_no_match() {
+        let err = find(&devices(), "Magic Keyboard").unwrap_err();
+        assert!(matches!(&err, Error::NoMatch(name) if name == "Magic Keyboard"));
+        assert_eq!(
+            err.to_string(),
+            "failed to find device matching name `Magic Keyboard`"
+        );
+    }
+
+    #[test]
+    fn find_multiple_matches() {
+        let devices = devices();
+        assert_eq!(
+            find(&devices, "Apple Internal Keyboard / Trackpad").unwrap(),
+            devices[0]
+        );
+        match find(&devices, "USB Keyboard").unwrap_err() {
+            Error::MultipleMatches(matches) => assert_eq!(matches, devices[1..]),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn list_hidutil_not_found() {
+        let err = list(Path::new("/nonexistent/hidutil")).unwrap_err();
+        assert!(matches!(&err, Error::HidutilNotFound(p) if p == "/nonexistent/hidutil"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_command_failed() {
+        let err = apply(Path::new("false"), &None, &[]).unwrap_err();
+        assert!(matches!(err, Error::CommandFailed(_)), "{:?}", err);
+    }
+}
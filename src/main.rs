@@ -1,16 +1,42 @@
-mod cmd;
-mod hex;
-mod hid;
-mod types;
+mod bundle;
+mod config;
+mod diagram;
+mod fuzzy;
+mod row;
+mod selector;
+mod share;
+mod spec;
+mod table;
+mod wake;
 
-use std::fmt::Write;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Result};
-use clap::Parser;
+use anyhow::{bail, Context, Error, Result};
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+use kb_remap::{hex, hid, types};
+use serde::Serialize;
+use terminal_size::Width;
 
+use crate::bundle::{Bundle, DeviceMappings};
+use crate::config::{Alias, Config, Defaults, Profile};
 use crate::hex::Hex;
 use crate::hid::Device;
-use crate::types::{Map, Mappings};
+use crate::row::Row;
+use crate::selector::Selector;
+use crate::share::Share;
+use crate::spec::Spec;
+use crate::table::{tabulate, tabulate_fit, Column};
+use crate::types::{Key, Map, Mappings};
 
 const HELP_TEMPLATE: &str = "\
 {before-help}{bin} {version}
@@ -22,6 +48,13 @@ const HELP_TEMPLATE: &str = "\
 
 {all-args}{after-help}";
 
+/// The version of the shape of the JSON output, this is bumped whenever the
+/// shape changes.
+const JSON_SCHEMA: u32 = 1;
+
+/// How often the keyboards are listed to check for a reconnect when watching.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
 #[derive(Debug, Parser)]
 #[clap(
     author,
@@ -34,14 +67,194 @@ struct Opt {
     #[clap(long, conflicts_with_all = &["reset", "dump", "swap", "map"])]
     list: bool,
 
+    /// The comma separated columns to show when listing keyboards.
+    ///
+    /// Possible values: vendor, product, name, serial, status, location,
+    /// transport.
+    #[clap(long, value_name = "COLUMNS", value_delimiter = ',', requires = "list")]
+    columns: Vec<Column>,
+
+    /// Do not truncate long names to fit the terminal when listing keyboards.
+    #[clap(long, requires = "list")]
+    full_names: bool,
+
+    /// Group keyboards that share a vendor and product ID when listing them.
+    ///
+    /// Keyboards are matched by ID, so this shows which of them are modified
+    /// together.
+    #[clap(long, requires = "list", conflicts_with = "raw")]
+    group_by_id: bool,
+
+    /// Group keyboards under a header for each vendor when listing them.
+    ///
+    /// Possible values: vendor.
+    #[clap(long, value_name = "FIELD", requires = "list", conflicts_with_all = &["raw", "json"])]
+    group_by: Option<GroupBy>,
+
+    /// Print the unmodified `hidutil list` output when listing keyboards.
+    #[clap(long, requires = "list", conflicts_with = "columns")]
+    raw: bool,
+
     /// Reset the keyboard mapping.
-    #[clap(long, conflicts_with_all = &["list", "swap", "map"],  short_alias = 'R', alias = "RESET")]
-    reset: bool,
+    ///
+    /// If keys are given only the modifications of those keys are reset and
+    /// the rest are kept.
+    #[clap(
+        long,
+        value_name = "KEY",
+        num_args = 0..,
+        conflicts_with_all = &["list", "swap", "map"],
+        short_alias = 'R',
+        alias = "RESET"
+    )]
+    reset: Option<Vec<Key>>,
 
-    /// Dump the raw hidutil command that would be executed.
-    #[clap(long)]
+    /// Reset each available keyboard individually instead of globally.
+    #[clap(long, requires = "reset", conflicts_with_all = &["name", "name_fuzzy", "vendor_id", "product_id", "serial"])]
+    all_devices: bool,
+
+    /// Modify each selected keyboard individually instead of failing when
+    /// multiple keyboards are selected.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "all_devices", "each_confirm", "json", "share", "get"]
+    )]
+    all: bool,
+
+    /// Ask whether to modify each selected keyboard individually instead of
+    /// failing when multiple keyboards are selected.
+    #[clap(long, conflicts_with_all = &["list", "all_devices", "json", "share"])]
+    each_confirm: bool,
+
+    /// When modifying multiple keyboards, keep going if one of them fails and
+    /// report the failures at the end instead of stopping at the first one.
+    #[clap(long, conflicts_with_all = &["list", "dump", "json", "share", "watch"])]
+    continue_on_error: bool,
+
+    /// Keep running and apply the mappings again whenever the selected
+    /// keyboard is connected.
+    ///
+    /// If the keyboard is not connected yet it is waited for. Press Ctrl-C to
+    /// stop watching.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "reset", "dump", "json", "share", "all", "each_confirm"]
+    )]
+    watch: bool,
+
+    /// Remap what the function keys do when pressed together with fn instead
+    /// of remapping the keys themselves.
+    ///
+    /// This sets the `AppleFnUsageMap` property used by Apple internal
+    /// keyboards, for example to swap the media and function behavior of a
+    /// function key. Only the function keys F1 to F24 can be mapped, to keys on
+    /// the Keyboard/Keypad (0x7), Consumer (0xc), or Apple Vendor Top Case
+    /// (0xff) usage pages.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "reset", "all", "each_confirm", "all_devices", "probe", "get", "diff",
+            "explain_device", "share", "watch", "json", "format", "preview", "diagram",
+        ]
+    )]
+    apple_fn: bool,
+
+    /// Show which keyboards currently remap this key and to what.
+    #[clap(long, value_name = "KEY", conflicts_with_all = &["list", "reset", "dump", "swap", "map"])]
+    probe: Option<Key>,
+
+    /// Print a diagram of a keyboard showing the mappings instead of applying
+    /// them.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "reset", "probe", "dump", "json", "share", "explain_device"]
+    )]
+    diagram: bool,
+
+    /// Print what each modifier key produces after the mappings instead of
+    /// applying them.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "reset", "probe", "diagram", "dump", "json", "share", "explain_device"]
+    )]
+    preview: bool,
+
+    /// Print the modifications currently applied to the selected keyboard.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "reset", "all_devices", "each_confirm", "probe", "diagram", "dump", "json",
+            "swap", "map", "share",
+        ]
+    )]
+    get: bool,
+
+    /// Print how the modifications would change the ones currently applied to
+    /// the selected keyboard instead of applying them.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "all_devices", "each_confirm", "all", "probe", "diagram", "dump", "json",
+            "share", "get", "watch",
+        ]
+    )]
+    diff: bool,
+
+    /// Print everything known about the selected keyboard.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "reset", "probe", "dump", "json", "swap", "map", "share"]
+    )]
+    explain_device: bool,
+
+    /// Dump the raw hidutil command that would be executed instead of
+    /// executing it.
+    ///
+    /// Also available as `--dry-run`. Nothing is modified in any mode that
+    /// accepts it, and the modes that can't print what they would do reject it.
+    #[clap(long, alias = "dry-run")]
     dump: bool,
 
+    /// The format of the command output by `--dump`.
+    ///
+    /// Possible values: shell, applescript, plist. The plist format only
+    /// contains the `UserKeyMapping` property, not the keyboard selection.
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        requires = "dump",
+        default_value = "shell"
+    )]
+    format: Format,
+
+    /// Do not ask for confirmation before modifying every keyboard.
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Only print errors and the output that was asked for.
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print warnings about the `hidutil` output and the commands executed.
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// Do not color the output. Color is also disabled by setting `NO_COLOR`.
+    #[clap(long)]
+    no_color: bool,
+
+    /// Output the result as JSON instead of human readable text.
+    #[clap(long, conflicts_with_all = &["raw", "columns", "dump", "probe", "all_devices"])]
+    json: bool,
+
+    /// Print a completion script for the given shell and exit.
+    #[clap(long, value_name = "SHELL")]
+    completions: Option<Shell>,
+
+    /// Output human readable text even if the config file sets `json = true`.
+    #[clap(long, conflicts_with = "json")]
+    no_json: bool,
+
     /// Swap two keys. Equivalent to two `map` options.
     #[clap(short, long, value_name = "SRC:DST")]
     swap: Vec<Mappings>,
@@ -50,123 +263,3526 @@ struct Opt {
     #[clap(short, long, value_name = "SRC:DST")]
     map: Vec<Mappings>,
 
+    /// Map each key in a row to the key N positions to the right of it.
+    ///
+    /// Possible rows: number, top, home, bottom. A negative N shifts to the
+    /// left instead. Keys wrap around at the ends of the row.
+    #[clap(
+        long,
+        value_names = &["ROW", "N"],
+        num_args = 2,
+        allow_negative_numbers = true,
+        conflicts_with_all = &["list", "from_share", "profile", "host_profile"]
+    )]
+    shift_row: Vec<String>,
+
+    /// Print a token encoding the keyboard selection and mappings instead of
+    /// applying them.
+    #[clap(long, conflicts_with_all = &["list", "reset", "probe", "dump", "json", "from_share"])]
+    share: bool,
+
+    /// Apply the keyboard selection and mappings from a `--share` token.
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        conflicts_with_all = &["list", "probe", "swap", "map", "name", "name_fuzzy", "search", "vendor_id", "product_id", "serial", "transport", "location_id", "registry_id"]
+    )]
+    from_share: Option<String>,
+
+    /// Apply the keyboard selection and mappings from a named profile in the
+    /// config file.
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = &["list", "swap", "map", "from_share"]
+    )]
+    profile: Option<String>,
+
+    /// Apply the profile in the config file scoped to this Mac's hostname.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "swap", "map", "from_share", "profile", "toggle"]
+    )]
+    host_profile: bool,
+
+    /// Toggle between two profiles in the config file, applying the second if
+    /// the first is currently applied and the first otherwise.
+    ///
+    /// The keyboard is selected by the command line or the first profile.
+    #[clap(
+        long,
+        value_names = &["PROFILE", "PROFILE"],
+        num_args = 2,
+        conflicts_with_all = &["list", "reset", "swap", "map", "from_share", "profile", "host_profile", "watch"]
+    )]
+    toggle: Vec<String>,
+
+    /// Apply the mappings to each keyboard selected by an alias in the named
+    /// group in the config file.
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = &[
+            "list", "from_share", "profile", "host_profile", "toggle", "spec", "all", "all_devices",
+            "each_confirm", "export_all", "import_all", "watch", "share", "name", "name_fuzzy",
+            "search", "vendor_id", "product_id", "serial", "transport", "location_id",
+            "registry_id",
+        ]
+    )]
+    group: Option<String>,
+
+    /// Apply the keyboard selection and mappings from a JSON spec file.
+    ///
+    /// This is a simple format meant to be generated by other tools, e.g.
+    /// `{"device":{"vendor_id":"0xc45"},"maps":[{"from":"capslock","to":"lcontrol"}]}`.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["list", "swap", "map", "shift_row", "from_share", "profile", "host_profile"]
+    )]
+    spec: Option<PathBuf>,
+
+    /// Read mappings from standard input as a JSON array, e.g.
+    /// `[{"src":"capslock","dst":"lcontrol"}]`.
+    ///
+    /// The keys use the same names as `--map`. Since standard input is used for
+    /// the mappings, pass `--yes` if a confirmation would be needed.
+    #[clap(
+        long,
+        conflicts_with_all = &["list", "reset", "each_confirm", "from_share", "profile", "host_profile", "spec"]
+    )]
+    stdin: bool,
+
+    /// The path to the config file.
+    ///
+    /// Defaults to `~/.config/kb-remap/config.toml`.
+    #[clap(long, value_name = "FILE", env = "KB_REMAP_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Write the config file and the modifications applied to every keyboard
+    /// to this file, e.g. to move them to another machine.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["list", "reset", "swap", "map", "profile", "host_profile", "from_share", "import_all"]
+    )]
+    export_all: Option<PathBuf>,
+
+    /// Restore the config file and the modifications applied to every keyboard
+    /// from a file written by `--export-all`.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["list", "reset", "swap", "map", "profile", "host_profile", "from_share"]
+    )]
+    import_all: Option<PathBuf>,
+
+    /// Compare the modifications applied to every keyboard with a file written
+    /// by `--export-all`, failing if any of them differ.
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &[
+            "list", "reset", "swap", "map", "profile", "host_profile", "from_share", "export_all",
+            "import_all", "dump", "json", "watch",
+        ]
+    )]
+    compare: Option<PathBuf>,
+
+    /// Open the config file in `$VISUAL` or `$EDITOR`, creating it if it does
+    /// not exist, and check that it is valid afterwards.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "reset", "swap", "map", "profile", "host_profile", "from_share", "export_all",
+            "import_all", "dump",
+        ]
+    )]
+    edit_config: bool,
+
+    /// Append a mapping to a profile in the config file, creating the profile
+    /// if it does not exist. Comments and formatting in the file are kept.
+    #[clap(
+        long,
+        value_names = &["PROFILE", "SRC:DST"],
+        num_args = 2,
+        conflicts_with_all = &[
+            "list", "reset", "swap", "map", "profile", "host_profile", "from_share", "export_all",
+            "import_all", "edit_config",
+        ]
+    )]
+    append_map: Vec<String>,
+
+    /// Sort the mappings so that the output is the same regardless of the
+    /// order the options were given in.
+    #[clap(long)]
+    sort_mappings: bool,
+
+    /// Fail if more than this many keyboards would be affected.
+    #[clap(long, value_name = "N")]
+    max_matches: Option<usize>,
+
+    /// Ask for confirmation before applying more than this many mappings.
+    ///
+    /// This catches mistakes in long chains of mappings before they reach the
+    /// keyboard. The confirmation is skipped when stdin is not a terminal or
+    /// when `--yes` is given.
+    #[clap(long, value_name = "N", default_value = "8")]
+    confirm_count: usize,
+
     /// Select the first keyboard with this name.
+    ///
+    /// Prefix the name with `!` to instead select every keyboard except the
+    /// ones with this name. Repeat the option to select a keyboard with any of
+    /// the names.
     #[clap(long, value_name = "NAME")]
-    name: Option<String>,
+    name: Vec<String>,
+
+    /// Select the keyboard with the name that best matches this query.
+    #[clap(long, value_name = "QUERY", conflicts_with = "name")]
+    name_fuzzy: Option<String>,
+
+    /// Select the keyboards with a name, manufacturer, or serial number that
+    /// contains this query, ignoring case.
+    #[clap(
+        long,
+        value_name = "QUERY",
+        conflicts_with_all = &["name", "name_fuzzy", "vendor_id", "product_id", "serial", "share"]
+    )]
+    search: Option<String>,
 
     /// Select the first keyboard with this vendor ID.
     #[clap(long, value_name = "VENDOR-ID")]
     vendor_id: Option<Hex>,
 
     /// Select the first keyboard with this product ID.
+    ///
+    /// Repeat the option to select a keyboard with any of the product IDs.
     #[clap(long, value_name = "PRODUCT-ID")]
-    product_id: Option<Hex>,
+    product_id: Vec<Hex>,
+
+    /// Select the keyboard with this serial number.
+    ///
+    /// This is useful for Bluetooth keyboards since it stays the same across
+    /// reconnects.
+    #[clap(long, value_name = "SERIAL")]
+    serial: Option<String>,
+
+    /// Select the keyboard connected with this transport, e.g. `USB` or
+    /// `Bluetooth`.
+    ///
+    /// This distinguishes a keyboard that is connected with more than one
+    /// transport at the same time, the case is ignored.
+    #[clap(long, value_name = "TRANSPORT", conflicts_with = "share")]
+    transport: Option<String>,
+
+    /// Select the keyboard at this location ID.
+    ///
+    /// This distinguishes identical keyboards that are connected at the same
+    /// time, use the `location` column when listing keyboards to find it.
+    #[clap(long, value_name = "LOCATION-ID", conflicts_with = "share")]
+    location_id: Option<Hex>,
+
+    /// Select the keyboard service with this registry ID, as shown in the
+    /// `hidutil list` output.
+    #[clap(
+        long,
+        value_name = "REGISTRY-ID",
+        conflicts_with_all = &[
+            "name", "name_fuzzy", "search", "vendor_id", "product_id", "serial", "transport",
+            "location_id", "share",
+        ]
+    )]
+    registry_id: Option<Hex>,
+
+    /// The path to the `hidutil` program.
+    #[clap(
+        long,
+        value_name = "PATH",
+        env = "KB_REMAP_HIDUTIL",
+        default_value = "hidutil"
+    )]
+    hidutil_path: PathBuf,
+
+    /// Read the `hidutil list` output from this file instead of running
+    /// `hidutil`, this is useful for debugging parsing issues.
+    #[clap(long, value_name = "FILE", hide = true)]
+    hidutil_output: Option<PathBuf>,
+
+    /// Reuse the `hidutil list` output from a run in the last few seconds.
+    ///
+    /// This speeds up running many commands back to back, for example from a
+    /// script. The output is cached in the temporary directory.
+    #[clap(long, conflicts_with = "hidutil_output")]
+    cache: bool,
+
+    /// Remove the files cached by `--cache` and exit.
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "list", "reset", "swap", "map", "profile", "host_profile", "from_share", "export_all",
+            "import_all", "edit_config", "append_map", "cache",
+        ]
+    )]
+    clear_cache: bool,
+}
+
+/// The format of the command output by `--dump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Shell,
+    AppleScript,
+    Plist,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let format = match s {
+            "shell" => Self::Shell,
+            "applescript" => Self::AppleScript,
+            "plist" => Self::Plist,
+            f => bail!(
+                "unknown format `{}`, expected one of `shell`, `applescript`, or `plist`",
+                f
+            ),
+        };
+        Ok(format)
+    }
+}
+
+/// The field to group keyboards by when listing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupBy {
+    Vendor,
+}
+
+impl FromStr for GroupBy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "vendor" => Ok(Self::Vendor),
+            g => bail!("unknown grouping `{}`, expected `vendor`", g),
+        }
+    }
+}
+
+/// The option that a mapping was given by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Swap,
+    Map,
 }
 
 impl Opt {
+    /// The keys given to `--reset`, empty if every key should be reset.
+    fn reset_keys(&self) -> &[Key] {
+        self.reset.as_deref().unwrap_or_default()
+    }
+
+    /// The usages of the keys given to `--reset`.
+    fn reset_usages(&self) -> Result<Vec<u64>> {
+        self.reset_keys().iter().map(hid::usage).collect()
+    }
+
     /// Flatten all the mappings into a single list.
     fn mappings(&self) -> Vec<Map> {
-        self.swap
+        self.mappings_with_origin()
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect()
+    }
+
+    /// Flatten all the mappings into a single list, along with the option
+    /// that each one came from.
+    ///
+    /// The two mappings of a swap are always kept next to each other, even
+    /// when sorting.
+    fn mappings_with_origin(&self) -> Vec<(Map, Origin)> {
+        let mut groups: Vec<Vec<_>> = self
+            .swap
             .iter()
-            .flat_map(|Mappings(mappings)| mappings.iter().flat_map(|m| [*m, m.swapped()]))
+            .flat_map(|Mappings(mappings)| mappings.iter())
+            .map(|m| vec![(*m, Origin::Swap), (m.swapped(), Origin::Swap)])
             .chain(
                 self.map
                     .iter()
-                    .flat_map(|Mappings(mappings)| mappings.iter().cloned()),
+                    .flat_map(Mappings::to_pairs)
+                    .map(|(src, dst)| vec![(Map::new(src, dst), Origin::Map)]),
             )
-            .collect()
+            .collect();
+        if self.sort_mappings {
+            for group in &mut groups {
+                group.sort_by_key(|(m, _)| types::sort_key(m));
+            }
+            groups.sort_by_key(|group| types::sort_key(&group[0].0));
+        }
+        groups.into_iter().flatten().collect()
+    }
+
+    /// Returns the `hidutil list` output.
+    fn list_raw(&self) -> Result<String> {
+        match &self.hidutil_output {
+            Some(path) => fs::read_to_string(path)
+                .with_context(|| format!("failed to read `{}`", path.display())),
+            None if self.cache => hid::list_raw_cached(
+                &self.hidutil_path,
+                &hid::list_cache_path(&self.hidutil_path),
+            ),
+            None => hid::list_raw(&self.hidutil_path),
+        }
+    }
+
+    /// List available HID devices.
+    fn devices(&self) -> Result<Vec<Device>> {
+        self.parse_list(&self.list_raw()?)
+    }
+
+    /// Parse the devices from the `hidutil list` output.
+    fn parse_list(&self, output: &str) -> Result<Vec<Device>> {
+        self.warn_list_output(output);
+        hid::parse_list(output)
+    }
+
+    /// Warn if the `hidutil list` output has no devices, unless `--quiet` was
+    /// given, and about any IDs that are parsed as decimal, only if
+    /// `--verbose` was given since they are usually fine.
+    fn warn_list_output(&self, output: &str) {
+        if !self.quiet && !hid::has_devices(output) {
+            eprintln!("warning: found no devices in `hidutil list` output");
+        }
+        if self.verbose {
+            for id in hid::decimal_ids(output) {
+                eprintln!(
+                    "warning: parsed ID `{}` as decimal since it has no `0x` prefix",
+                    id
+                );
+            }
+        }
+    }
+
+    /// Returns the keyboard selection and mappings as a shareable value.
+    fn to_share(&self) -> Result<Share> {
+        if self.name.len() > 1 || self.product_id.len() > 1 {
+            bail!("only a single `--name` and `--product-id` can be shared");
+        }
+        Ok(Share {
+            name: self.name.first().cloned(),
+            name_fuzzy: self.name_fuzzy.clone(),
+            vendor_id: self.vendor_id.map(|Hex(id)| id),
+            product_id: self.product_id.first().map(|Hex(id)| *id),
+            serial: self.serial.clone(),
+            mappings: self.mappings(),
+        })
+    }
+
+    /// Replace the keyboard selection and mappings with the shared ones.
+    fn set_share(&mut self, share: Share) {
+        self.name = share.name.into_iter().collect();
+        self.name_fuzzy = share.name_fuzzy;
+        self.vendor_id = share.vendor_id.map(Hex);
+        self.product_id = share.product_id.map(Hex).into_iter().collect();
+        self.serial = share.serial;
+        self.swap = Vec::new();
+        self.map = vec![Mappings(share.mappings)];
+    }
+
+    /// Use the mappings from the profile, along with its keyboard selection
+    /// unless one was given on the command line.
+    fn set_profile(&mut self, profile: Profile) {
+        if self.name.is_empty() {
+            self.name.extend(profile.name);
+        }
+        self.vendor_id = self.vendor_id.or(profile.vendor_id.map(Hex));
+        if self.product_id.is_empty() {
+            self.product_id.extend(profile.product_id.map(Hex));
+        }
+        self.swap = profile.swap;
+        self.map = profile.map;
+    }
+
+    /// Use the mappings from the spec, along with its keyboard selection unless
+    /// one was given on the command line.
+    fn set_spec(&mut self, spec: Spec) {
+        let mappings = spec.mappings();
+        let device = spec.device;
+        if self.name.is_empty() {
+            self.name.extend(device.name);
+        }
+        self.vendor_id = self.vendor_id.or(device.vendor_id.map(Hex));
+        if self.product_id.is_empty() {
+            self.product_id.extend(device.product_id.map(Hex));
+        }
+        self.serial = self.serial.take().or(device.serial);
+        self.swap = Vec::new();
+        self.map = vec![Mappings(mappings)];
+    }
+
+    /// Use the defaults from the config file for any options that were not
+    /// given on the command line.
+    fn set_defaults(&mut self, defaults: Defaults) {
+        let json_allowed = !self.no_json
+            && !self.raw
+            && self.columns.is_empty()
+            && !self.dump
+            && !self.all_devices
+            && self.group_by.is_none();
+        if json_allowed && defaults.json == Some(true) {
+            self.json = true;
+        }
+        if !self.quiet && !self.verbose {
+            self.quiet = defaults.quiet == Some(true);
+            self.verbose = defaults.verbose == Some(true);
+        }
+        if defaults.no_color == Some(true) {
+            self.no_color = true;
+        }
+        if !self.list && !self.all_devices && !self.has_selector() {
+            self.name = defaults.name.into_iter().collect();
+            self.vendor_id = defaults.vendor_id.map(Hex);
+            self.product_id = defaults.product_id.map(Hex).into_iter().collect();
+        }
+    }
+
+    /// Whether any option selecting a keyboard was given.
+    fn has_selector(&self) -> bool {
+        !self.selector().is_empty() || self.registry_id.is_some()
+    }
+
+    /// Returns the keyboard selection.
+    fn selector(&self) -> Selector {
+        Selector {
+            names: self.name.clone(),
+            name_fuzzy: self.name_fuzzy.clone(),
+            search: self.search.clone(),
+            vendor_id: self.vendor_id.map(|Hex(id)| id),
+            product_ids: self.product_id.iter().map(|Hex(id)| *id).collect(),
+            serial: self.serial.clone(),
+            transport: self.transport.clone(),
+            location_id: self.location_id.map(|Hex(id)| id),
+        }
+    }
+
+    /// Returns the path to the config file.
+    fn config_path(&self) -> Result<PathBuf> {
+        match &self.config {
+            Some(path) => Ok(path.clone()),
+            None => Config::default_path(),
+        }
     }
 }
 
 fn main() -> Result<()> {
-    let opt = Opt::parse();
+    let mut opt = Opt::parse();
+    if let Some(shell) = opt.completions {
+        clap_complete::generate(shell, &mut Opt::command(), "kb-remap", &mut io::stdout());
+        return Ok(());
+    }
+    if opt.edit_config {
+        return edit_config(&opt.config_path()?, run_editor, |err| {
+            eprintln!("error: {:#}", err);
+            confirm("Edit the config file again?")
+        });
+    }
+    if opt.clear_cache {
+        return clear_cache(&opt, &hid::cache_dir());
+    }
+    if let [profile, mapping] = opt.append_map.as_slice() {
+        return append_map(&opt, profile, mapping);
+    }
+    if let Some(token) = opt.from_share.take() {
+        opt.set_share(Share::decode(&token)?);
+    }
+    if let Some(name) = opt.profile.take() {
+        let config = Config::read(&opt.config_path()?)?;
+        opt.set_profile(config.profile(&name)?.clone());
+    }
+    if opt.host_profile {
+        let config = Config::read(&opt.config_path()?)?;
+        let hostname = hostname()?;
+        let (name, profile) = config.host_profile(&hostname)?;
+        if opt.verbose {
+            println!("Using profile `{}` for hostname `{}`", name, hostname);
+        }
+        opt.set_profile(profile.clone());
+    }
+    if let [first, second] = std::mem::take(&mut opt.toggle).as_slice() {
+        let config = Config::read(&opt.config_path()?)?;
+        opt.set_profile(config.profile(first)?.clone());
+        let (d, _) = select(&opt, || opt.devices())?;
+        let current = hid::current(&opt.hidutil_path, &d)?;
+        let (name, profile) = match toggle_index(&current, &opt.mappings())? {
+            0 => (first, config.profile(first)?),
+            _ => (second, config.profile(second)?),
+        };
+        if !opt.quiet && !opt.json {
+            println!("Toggling to profile `{}`", name);
+        }
+        opt.swap = profile.swap.clone();
+        opt.map = profile.map.clone();
+        // a profile without mappings is the default layout
+        if opt.mappings().is_empty() {
+            opt.reset = Some(Vec::new());
+        }
+    }
+    if let Some(path) = opt.spec.take() {
+        opt.set_spec(Spec::read(&path)?);
+    }
+    if opt.stdin {
+        let mappings = read_mappings(io::stdin().lock())?;
+        opt.map.push(mappings);
+    }
+    if let [row, n] = opt.shift_row.as_slice() {
+        let mappings = shift_row(row, n)?;
+        opt.map.push(mappings);
+    }
+    // the import and export read and write the config file themselves
+    if opt.import_all.is_none() && opt.export_all.is_none() {
+        let config = opt
+            .config_path()
+            .and_then(|path| Config::read_if_exists(&path));
+        let defaults = config_defaults(config, opt.config.is_some(), opt.quiet)?;
+        opt.set_defaults(defaults);
+    }
     if opt.list {
-        list()
+        list(&opt)
+    } else if let Some(path) = &opt.export_all {
+        export_all(&opt, path, || opt.devices())
+    } else if let Some(path) = &opt.import_all {
+        import_all(&opt, path)
+    } else if let Some(path) = &opt.compare {
+        compare(&opt, path, || opt.devices())
+    } else if opt.all_devices {
+        reset_all(&opt, || opt.devices())
+    } else if opt.all || opt.each_confirm {
+        apply_each(&opt)
+    } else if let Some(name) = &opt.group {
+        let config = Config::read(&opt.config_path()?)?;
+        apply_group(&opt, &config.group(name)?, || opt.devices())
+    } else if let Some(key) = opt.probe {
+        probe(&opt, key)
+    } else if opt.diagram {
+        print!("{}", diagram::render(&opt.mappings()));
+        Ok(())
+    } else if opt.preview {
+        print!("{}", render_preview(&opt.mappings()));
+        Ok(())
+    } else if opt.get {
+        get(&opt)
+    } else if opt.diff {
+        diff(&opt)
+    } else if opt.explain_device {
+        explain_device(&opt)
+    } else if opt.share {
+        println!("{}", opt.to_share()?.encode()?);
+        Ok(())
+    } else if opt.watch {
+        watch(&opt)
+    } else if opt.apple_fn {
+        apple_fn(&opt)
     } else {
-        apply(&opt)
+        apply(&opt, || opt.devices())
     }
 }
 
-fn list() -> Result<()> {
-    print!("{}", tabulate(hid::list()?));
-    Ok(())
+/// Parse the `--shift-row` values into the mappings for the row.
+fn shift_row(row: &str, n: &str) -> Result<Mappings> {
+    let row: Row = row.parse()?;
+    let n: i64 = n
+        .parse()
+        .with_context(|| format!("failed to parse `{}` as a number of keys", n))?;
+    Ok(row.shift(n))
 }
 
-fn apply(opt: &Opt) -> Result<()> {
-    let mut devices = hid::list()?;
-    let total = devices.len();
-    let mappings = opt.mappings();
+/// Read a JSON array of mappings like the `applied` mappings in the `--json`
+/// output.
+fn read_mappings(reader: impl io::Read) -> Result<Mappings> {
+    let mappings: Vec<Map> =
+        serde_json::from_reader(reader).context("failed to parse mappings from stdin")?;
+    Ok(Mappings(mappings))
+}
 
-    if let Some(name) = &opt.name {
-        devices.retain(|d| d.name == *name);
-        if devices.is_empty() {
-            bail!("failed to find device matching name `{}`", name)
+/// Edit the config file, creating it from the template if it does not exist.
+///
+/// After each edit the config file is parsed and if it is invalid `retry` is
+/// called with the error to decide whether to edit it again.
+fn edit_config(
+    path: &Path,
+    mut edit: impl FnMut(&Path) -> Result<()>,
+    mut retry: impl FnMut(&Error) -> Result<bool>,
+) -> Result<()> {
+    if !path.exists() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create `{}`", dir.display()))?;
         }
+        fs::write(path, Config::TEMPLATE)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
     }
-
-    if let Some(Hex(vendor_id)) = opt.vendor_id {
-        devices.retain(|d| d.vendor_id == vendor_id);
-        if devices.is_empty() {
-            bail!("failed to find device matching vendor id `{}`", vendor_id)
+    loop {
+        edit(path)?;
+        match Config::read(path) {
+            Ok(_) => return Ok(()),
+            Err(err) if retry(&err)? => continue,
+            Err(err) => return Err(err),
         }
     }
+}
 
-    if let Some(Hex(product_id)) = opt.product_id {
-        devices.retain(|d| d.product_id == product_id);
-        if devices.is_empty() {
-            bail!("failed to find device matching product id `{}`", product_id)
-        }
-    }
+/// Returns the index of the profile to apply to toggle between two profiles:
+/// the second if the mappings of the first are currently applied and the
+/// first otherwise.
+fn toggle_index(current: &[(u64, u64)], first: &[Map]) -> Result<usize> {
+    Ok(usize::from(hid::is_applied(current, first)?))
+}
 
-    let d = if devices.len() == 1 {
-        Some(devices.remove(0))
-    } else if devices.len() != total {
-        bail!("multiple devices matching filter:\n{}", tabulate(devices))
+fn clear_cache(opt: &Opt, dir: &Path) -> Result<()> {
+    let files = if opt.dump {
+        hid::cache_files(dir)?
     } else {
-        None
+        hid::clear_cache(dir)?
     };
+    if opt.quiet {
+        return Ok(());
+    }
+    let verb = if opt.dump { "Would delete" } else { "Deleted" };
+    for path in &files {
+        println!("{} `{}`", verb, path.display());
+    }
+    if files.is_empty() {
+        println!("No cached files in `{}`", dir.display());
+    }
+    Ok(())
+}
 
+fn append_map(opt: &Opt, profile: &str, mapping: &str) -> Result<()> {
+    let path = opt.config_path()?;
+    if config::is_yaml(&path) {
+        bail!("`--append-map` only supports TOML config files");
+    }
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read `{}`", path.display()))
+        }
+    };
+    let contents = Config::append_map(&contents, profile, mapping)
+        .with_context(|| format!("failed to update `{}`", path.display()))?;
     if opt.dump {
-        if opt.reset {
-            println!("{}", hid::dump(&d, &[])?);
-        } else if !mappings.is_empty() {
-            println!("{}", hid::dump(&d, &mappings)?);
+        // print what would be written instead
+        print!("{}", contents);
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create `{}`", dir.display()))?;
+    }
+    fs::write(&path, contents).with_context(|| format!("failed to write `{}`", path.display()))?;
+    if !opt.quiet {
+        println!(
+            "Added `{}` to profile `{}` in `{}`",
+            mapping,
+            profile,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Open the file in the user's editor and wait for it to exit.
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    // the editor can include arguments, e.g. `code --wait`
+    let mut args = editor.split_whitespace();
+    let program = args.next().context("editor is empty")?;
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()
+        .with_context(|| format!("failed to run editor `{}`", editor))?;
+    if !status.success() {
+        bail!("editor `{}` exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Returns the hostname of this machine.
+fn hostname() -> Result<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for writes of its length
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return Err(io::Error::last_os_error()).context("failed to get the hostname");
+    }
+    // the hostname is truncated without a nul terminator if it is too long
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+fn list(opt: &Opt) -> Result<()> {
+    if opt.json {
+        let mut devices = opt.devices()?;
+        if opt.group_by_id {
+            devices = hid::group_by_id(&devices);
         }
+        println!("{}", list_json(&devices)?);
+        return Ok(());
+    }
+    let columns = match opt.columns.as_slice() {
+        [] => Column::DEFAULT,
+        columns => columns,
+    };
+    // only fit the table to the terminal if the output is a terminal
+    let max_width = if opt.full_names {
+        None
     } else {
-        if let Some(d) = &d {
+        terminal_size::terminal_size().map(|(Width(w), _)| usize::from(w))
+    };
+    let output = opt.list_raw()?;
+    if !opt.raw {
+        opt.warn_list_output(&output);
+    }
+    print!(
+        "{}",
+        render_list(
+            &output,
+            opt.raw,
+            opt.group_by_id,
+            opt.group_by,
+            columns,
+            max_width
+        )?
+    );
+    Ok(())
+}
+
+/// Render the `hidutil list` output either as is or as a table of keyboards,
+/// optionally grouped by ID or under a header for each vendor, fitting the
+/// tables within the maximum width if given.
+fn render_list(
+    output: &str,
+    raw: bool,
+    group_by_id: bool,
+    group_by: Option<GroupBy>,
+    columns: &[Column],
+    max_width: Option<usize>,
+) -> Result<String> {
+    if raw {
+        return Ok(output.to_owned());
+    }
+    let mut devices = hid::parse_list(output)?;
+    if group_by_id {
+        devices = hid::group_by_id(&devices);
+    }
+    let render = |devices: &[Device]| match max_width {
+        Some(max_width) => tabulate_fit(devices, columns, max_width),
+        None => tabulate(devices, columns),
+    };
+    match group_by {
+        Some(GroupBy::Vendor) => {
+            let sections: Vec<_> = hid::group_by_vendor(&devices)
+                .into_iter()
+                .map(|(vendor_id, devices)| {
+                    let header = match devices.iter().find_map(|d| d.manufacturer.as_ref()) {
+                        Some(manufacturer) => format!("{:#x} ({})", vendor_id, manufacturer),
+                        None => format!("{:#x}", vendor_id),
+                    };
+                    format!("Vendor {}:\n{}", header, render(&devices))
+                })
+                .collect();
+            Ok(sections.join("\n"))
+        }
+        None => Ok(render(&devices)),
+    }
+}
+
+fn export_all(opt: &Opt, path: &Path, list: impl FnOnce() -> Result<Vec<Device>>) -> Result<()> {
+    let config_path = opt.config_path()?;
+    let config = match fs::read_to_string(&config_path) {
+        Ok(config) => Some(config),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read `{}`", config_path.display()))
+        }
+    };
+
+    let mut devices = Vec::new();
+    for d in hid::group_by_id(&list()?) {
+        let current = hid::current(&opt.hidutil_path, &Some(d.clone()))?;
+        if current.is_empty() {
+            continue;
+        }
+        let mappings = current
+            .into_iter()
+            .map(|(src, dst)| Ok(Map(Key::try_from(src)?, Key::try_from(dst)?)))
+            .collect::<Result<_>>()?;
+        devices.push(DeviceMappings {
+            vendor_id: d.vendor_id,
+            product_id: d.product_id,
+            name: d.name,
+            serial: d.serial,
+            mappings,
+        });
+    }
+
+    let bundle = Bundle { config, devices };
+    if opt.dump {
+        // print what would be written instead
+        print!("{}", bundle.to_json()?);
+        return Ok(());
+    }
+    bundle.write(path)?;
+    if !opt.quiet {
+        println!(
+            "Exported the config and {} keyboard(s) to `{}`",
+            bundle.devices.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+fn import_all(opt: &Opt, path: &Path) -> Result<()> {
+    let bundle = Bundle::read(path)?;
+
+    if opt.dump {
+        if bundle.config.is_some() {
             println!(
-                "Selected:\n  Vendor ID: 0x{:x}\n  Product ID: 0x{:x}\n  Name: {}\n",
-                d.vendor_id, d.product_id, d.name
+                "# write the config file to `{}`",
+                opt.config_path()?.display()
             );
         }
+        for d in &bundle.devices {
+            let device = Device::new(d.vendor_id, d.product_id, d.name.clone())
+                .with_serial(d.serial.clone());
+            println!("{}", dump(opt, &Some(device), &d.mappings)?);
+        }
+        return Ok(());
+    }
+
+    if bundle.config.is_some() {
+        let config_path = opt.config_path()?;
+        let existing = fs::read_to_string(&config_path).ok();
+        if existing.is_some()
+            && existing != bundle.config
+            && !opt.yes
+            && !confirm(&format!("Overwrite `{}`?", config_path.display()))?
+        {
+            bail!("aborted, use `--yes` to skip the confirmation")
+        }
+        bundle.write_config(&config_path)?;
+        if !opt.quiet {
+            println!("Wrote `{}`", config_path.display());
+        }
+    }
 
-        if opt.reset {
-            hid::apply(&d, &[])?;
-            println!("Reset all modifications");
-        } else if !mappings.is_empty() {
-            hid::apply(&d, &mappings)?;
-            println!("Applied the following modifications:");
-            for Map(src, dst) in mappings {
-                println!("  {:?} -> {:?}", src, dst);
-            }
+    modify_each(opt, &bundle.devices, |d| {
+        let device =
+            Device::new(d.vendor_id, d.product_id, d.name.clone()).with_serial(d.serial.clone());
+        run_apply(opt, &Some(device), &d.mappings)
+            .with_context(|| format!("failed to modify {}", d.name))?;
+        if !opt.quiet {
+            println!("Applied {} modification(s) to {}", d.mappings.len(), d.name);
+        }
+        Ok(())
+    })
+}
+
+fn reset_all(opt: &Opt, list: impl FnOnce() -> Result<Vec<Device>>) -> Result<()> {
+    let devices = list()?;
+    check_max_matches(devices.len(), opt.max_matches)?;
+    modify_each(opt, devices, |d| {
+        let mappings = reset_mappings(opt, &Some(d.clone()))?;
+        if opt.dump {
+            println!("{}", dump(opt, &Some(d), &mappings)?);
         } else {
-            println!("No modifications to apply");
+            run_apply(opt, &Some(d.clone()), &mappings)
+                .with_context(|| format!("failed to modify {}", d.name))?;
+            if !opt.quiet {
+                println!(
+                    "{} for {} (0x{:x}, 0x{:x})",
+                    reset_message(opt.reset_keys()),
+                    d.name,
+                    d.vendor_id,
+                    d.product_id
+                );
+            }
         }
+        Ok(())
+    })
+}
+
+fn apply_each(opt: &Opt) -> Result<()> {
+    check_conflicts(&opt.mappings())?;
+    let devices = opt.selector().filter(opt.devices()?)?;
+
+    let ask = opt.each_confirm && !opt.yes && !opt.dump;
+    if ask && !io::stdin().is_terminal() {
+        bail!(
+            "cannot ask for confirmation because stdin is not a terminal, use `--yes` \
+             to apply to every selected keyboard"
+        )
     }
+    let devices = choose_devices(devices, |d| {
+        if !ask {
+            return Ok(true);
+        }
+        confirm(&format!(
+            "Apply to {} (0x{:x}, 0x{:x})?",
+            d.name, d.vendor_id, d.product_id
+        ))
+    })?;
+    check_max_matches(devices.len(), opt.max_matches)?;
+
+    modify_each(opt, devices, |d| {
+        let mappings = if opt.reset.is_some() {
+            reset_mappings(opt, &Some(d.clone()))?
+        } else {
+            opt.mappings()
+        };
+        if opt.dump {
+            println!("{}", dump(opt, &Some(d), &mappings)?);
+        } else {
+            run_apply(opt, &Some(d.clone()), &mappings)
+                .with_context(|| format!("failed to modify {}", d.name))?;
+            if !opt.quiet {
+                println!(
+                    "Applied {} modifications to {} (0x{:x}, 0x{:x})",
+                    mappings.len(),
+                    d.name,
+                    d.vendor_id,
+                    d.product_id
+                );
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Apply the mappings to the keyboards selected by each alias in a group.
+fn apply_group(
+    opt: &Opt,
+    aliases: &[(&str, &Alias)],
+    list: impl FnOnce() -> Result<Vec<Device>>,
+) -> Result<()> {
+    check_conflicts(&opt.mappings())?;
+    let devices = list()?;
+    modify_each(opt, aliases, |(name, alias)| {
+        let selected = alias
+            .selector()
+            .filter(devices.clone())
+            .with_context(|| format!("failed to select `@{}`", name))?;
+        for d in selected {
+            let mappings = if opt.reset.is_some() {
+                reset_mappings(opt, &Some(d.clone()))?
+            } else {
+                opt.mappings()
+            };
+            if opt.dump {
+                println!("{}", dump(opt, &Some(d), &mappings)?);
+            } else {
+                run_apply(opt, &Some(d.clone()), &mappings)
+                    .with_context(|| format!("failed to modify {}", d.name))?;
+                if !opt.quiet {
+                    println!(
+                        "Applied {} modifications to {} (@{})",
+                        mappings.len(),
+                        d.name,
+                        name
+                    );
+                }
+            }
+        }
+        Ok(())
+    })
+}
 
+/// Modify each keyboard in turn, stopping at the first failure unless
+/// `--continue-on-error` was given, in which case every keyboard is attempted
+/// and the failures are reported at the end.
+fn modify_each<T>(
+    opt: &Opt,
+    items: impl IntoIterator<Item = T>,
+    mut modify: impl FnMut(T) -> Result<()>,
+) -> Result<()> {
+    let (mut succeeded, mut failed) = (0, 0);
+    for item in items {
+        match modify(item) {
+            Ok(()) => succeeded += 1,
+            Err(err) if opt.continue_on_error => {
+                eprintln!("error: {:#}", err);
+                failed += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if opt.continue_on_error && !opt.quiet {
+        let summary = format!("Modified {} keyboard(s), {} failed", succeeded, failed);
+        // keep the standard output valid JSON
+        if opt.json {
+            eprintln!("{}", summary);
+        } else {
+            println!("{}", summary);
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "failed to modify {} of {} keyboard(s)",
+            failed,
+            succeeded + failed
+        );
+    }
     Ok(())
 }
 
-fn tabulate(devices: Vec<Device>) -> String {
-    let mut s = String::from("Vendor ID  Product ID  Name\n");
-    s.push_str("---------  ----------  ----------------------------------\n");
+/// Returns the devices that were chosen.
+fn choose_devices(
+    devices: Vec<Device>,
+    mut choose: impl FnMut(&Device) -> Result<bool>,
+) -> Result<Vec<Device>> {
+    let mut chosen = Vec::new();
     for d in devices {
-        writeln!(
-            s,
-            "{:<#9x}  {:<#10x}  {}",
-            d.vendor_id, d.product_id, d.name,
-        )
-        .unwrap();
+        if choose(&d)? {
+            chosen.push(d);
+        }
+    }
+    Ok(chosen)
+}
+
+/// Dump the hidutil modification command in the chosen format.
+fn dump(opt: &Opt, device: &Option<Device>, mappings: &[Map]) -> Result<String> {
+    match opt.format {
+        Format::Shell => hid::dump(&opt.hidutil_path, device, mappings),
+        Format::AppleScript => hid::dump_applescript(&opt.hidutil_path, device, mappings),
+        Format::Plist => hid::dump_plist(mappings),
+    }
+}
+
+/// Describe how many mappings are about to be applied to the device.
+fn apply_count_message(count: usize, device: &Option<Device>) -> String {
+    let target = match device {
+        Some(d) if !d.name.is_empty() => d.name.clone(),
+        Some(d) => format!(
+            "vendor ID {:#x}, product ID {:#x}",
+            d.vendor_id, d.product_id
+        ),
+        None => "every keyboard".to_owned(),
+    };
+    format!("About to apply {} mappings to {}", count, target)
+}
+
+/// Whether to ask for confirmation before a modification.
+///
+/// Only interactive runs are asked, others proceed as if `--yes` was given so
+/// that scripts keep working.
+fn needs_confirm(interactive: bool, yes: bool) -> bool {
+    interactive && !yes
+}
+
+/// Whether to ask for confirmation before applying the number of mappings.
+fn needs_count_confirm(count: usize, threshold: usize, interactive: bool, yes: bool) -> bool {
+    count > threshold && needs_confirm(interactive, yes)
+}
+
+/// Fail if the number of affected devices exceeds the maximum.
+fn check_max_matches(count: usize, max: Option<usize>) -> Result<()> {
+    match max {
+        Some(max) if count > max => bail!(
+            "{} devices would be affected but `--max-matches` is {}, use a filter \
+             to narrow the selection",
+            count,
+            max
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn probe(opt: &Opt, key: Key) -> Result<()> {
+    let src = hid::usage(&key)?;
+    let mut states = Vec::new();
+    for d in opt.devices()? {
+        let mappings = hid::current(&opt.hidutil_path, &Some(d.clone()))?;
+        states.push((d, mappings));
+    }
+
+    let found = find_mapped(&states, src);
+    if found.is_empty() {
+        println!("No keyboards remap {}", key);
+    } else {
+        println!("The following keyboards remap {}:", key);
+        for (d, dst) in found {
+            println!(
+                "  {} (0x{:x}, 0x{:x}) -> {}",
+                d.name,
+                d.vendor_id,
+                d.product_id,
+                usage_name(dst)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn get(opt: &Opt) -> Result<()> {
+    let (d, _) = select(opt, || opt.devices())?;
+    let mappings = hid::current(&opt.hidutil_path, &d)?;
+    print!("{}", current_summary(&mappings));
+    Ok(())
+}
+
+/// Render the modifications currently applied.
+fn current_summary(mappings: &[(u64, u64)]) -> String {
+    if mappings.is_empty() {
+        return String::from("No modifications applied\n");
+    }
+    let mut s = String::from("The following modifications are applied:\n");
+    for &(src, dst) in mappings {
+        writeln!(s, "  {} -> {}", usage_name(src), usage_name(dst)).unwrap();
+    }
+    s
+}
+
+fn diff(opt: &Opt) -> Result<()> {
+    let (d, _) = select(opt, || opt.devices())?;
+    let current = hid::current(&opt.hidutil_path, &d)?;
+    let mappings = if opt.reset.is_some() {
+        reset_mappings(opt, &d)?
+    } else {
+        opt.mappings()
+    };
+    let desired = usages(&mappings)?;
+    print!(
+        "{}",
+        render_diff(
+            &hid::normalize(&current),
+            &hid::normalize(&desired),
+            use_color(opt)
+        )
+    );
+    Ok(())
+}
+
+/// Returns the source and destination usages of the mappings.
+fn usages(mappings: &[Map]) -> Result<Vec<(u64, u64)>> {
+    mappings
+        .iter()
+        .map(|Map(src, dst)| Ok((hid::usage(src)?, hid::usage(dst)?)))
+        .collect()
+}
+
+/// Compare the modifications applied to each keyboard with the ones saved by
+/// `--export-all`, showing the difference for each keyboard that drifted.
+fn compare(opt: &Opt, path: &Path, list: impl FnOnce() -> Result<Vec<Device>>) -> Result<()> {
+    let bundle = Bundle::read(path)?;
+    let devices = hid::group_by_id(&list()?);
+
+    let mut drifted = 0;
+    for d in &devices {
+        let current = hid::current(&opt.hidutil_path, &Some(d.clone()))?;
+        let saved = bundle
+            .devices
+            .iter()
+            .find(|s| (s.vendor_id, s.product_id) == d.id_key());
+        let desired = match saved {
+            Some(s) => usages(&s.mappings)?,
+            None => Vec::new(),
+        };
+        let (current, desired) = (hid::normalize(&current), hid::normalize(&desired));
+        if current != desired {
+            drifted += 1;
+            if !opt.quiet {
+                println!("{} (0x{:x}, 0x{:x}):", d.name, d.vendor_id, d.product_id);
+                print!("{}", render_diff(&current, &desired, use_color(opt)));
+            }
+        }
+    }
+    // a keyboard that is not connected can't have drifted
+    for s in &bundle.devices {
+        let id = (s.vendor_id, s.product_id);
+        if !opt.quiet && !devices.iter().any(|d| d.id_key() == id) {
+            eprintln!(
+                "warning: {} (0x{:x}, 0x{:x}) is not connected",
+                s.name, s.vendor_id, s.product_id
+            );
+        }
+    }
+
+    if drifted > 0 {
+        bail!(
+            "the modifications of {} keyboard(s) differ from `{}`",
+            drifted,
+            path.display()
+        );
+    }
+    if !opt.quiet {
+        println!("The modifications match `{}`", path.display());
+    }
+    Ok(())
+}
+
+/// Render the difference between the current and desired modifications.
+///
+/// Modifications are compared as a set of source and destination usages so
+/// the order they are given in does not matter. Removed and added
+/// modifications are colored red and green if `color` is set.
+fn render_diff(current: &[(u64, u64)], desired: &[(u64, u64)], color: bool) -> String {
+    let paint = |code: &str, line: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, line)
+        } else {
+            line
+        }
+    };
+    let mut s = String::new();
+    let mut changed = false;
+    for m @ &(src, dst) in current {
+        let line = if desired.contains(m) {
+            format!("  {} -> {}", usage_name(src), usage_name(dst))
+        } else {
+            changed = true;
+            paint(
+                "31",
+                format!("- {} -> {}", usage_name(src), usage_name(dst)),
+            )
+        };
+        writeln!(s, "{}", line).unwrap();
+    }
+    for m @ &(src, dst) in desired {
+        if !current.contains(m) {
+            changed = true;
+            let line = paint(
+                "32",
+                format!("+ {} -> {}", usage_name(src), usage_name(dst)),
+            );
+            writeln!(s, "{}", line).unwrap();
+        }
+    }
+    if !changed {
+        s.push_str("No changes\n");
+    }
+    s
+}
+
+fn explain_device(opt: &Opt) -> Result<()> {
+    let output = opt.list_raw()?;
+    let (d, _) = select(opt, || opt.parse_list(&output))?;
+    let Some(d) = d else {
+        bail!("no keyboard selected, use `--name` or another option to select one")
+    };
+    let fields = hid::parse_fields(&output, &d);
+    let mappings = hid::current(&opt.hidutil_path, &Some(d.clone()))?;
+    print!("{}", explanation(&d, &fields, &mappings));
+    Ok(())
+}
+
+/// Render everything known about the device.
+fn explanation(d: &Device, fields: &[Vec<(String, String)>], mappings: &[(u64, u64)]) -> String {
+    let mut s = format!(
+        "Device:\n  Vendor ID: 0x{:x}\n  Product ID: 0x{:x}\n",
+        d.vendor_id, d.product_id
+    );
+    if !d.name.is_empty() {
+        writeln!(s, "  Name: {}", d.name).unwrap();
+    }
+    if let Some(serial) = &d.serial {
+        writeln!(s, "  Serial Number: {}", serial).unwrap();
+    }
+    for (i, row) in fields.iter().enumerate() {
+        writeln!(s, "\nhidutil list entry {}:", i + 1).unwrap();
+        for (header, value) in row {
+            writeln!(s, "  {}: {}", header, value.replace('\n', " ")).unwrap();
+        }
+    }
+    s.push_str("\nCurrent modifications:\n");
+    if mappings.is_empty() {
+        s.push_str("  none\n");
+    }
+    for &(src, dst) in mappings {
+        writeln!(s, "  {} -> {}", usage_name(src), usage_name(dst)).unwrap();
+    }
+    s
+}
+
+/// Returns a readable name for the combined usage page and usage ID.
+fn usage_name(usage: u64) -> String {
+    match Key::try_from(usage) {
+        Ok(key) => key.to_string(),
+        Err(_) => format!("0x{:x}", usage),
+    }
+}
+
+/// Warn about any mappings that replace a different mapping for the same key
+/// that is currently set on the device.
+fn warn_overrides(opt: &Opt, device: &Option<Device>, mappings: &[Map]) -> Result<()> {
+    let current = hid::current(&opt.hidutil_path, device)?;
+    for (src, old, new) in find_overrides(&current, mappings)? {
+        eprintln!(
+            "warning: overriding existing remap of {}, {} -> {}",
+            usage_name(src),
+            usage_name(old),
+            usage_name(new)
+        );
+    }
+    Ok(())
+}
+
+/// Fail if the same source key is mapped to different destination keys, since
+/// only one of them would take effect.
+fn check_conflicts(mappings: &[Map]) -> Result<()> {
+    // compare the named keys so that a raw usage matches its named key
+    let pairs: Vec<_> = mappings
+        .iter()
+        .map(|m| (m.src().named(), m.dst().named()))
+        .collect();
+    let mut conflicts = Vec::new();
+    for &(src, dst) in &pairs {
+        if pairs.iter().any(|&(s, d)| s == src && d != dst) && !conflicts.contains(&(src, dst)) {
+            conflicts.push((src, dst));
+        }
+    }
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let conflicts: Vec<_> = conflicts
+        .iter()
+        .map(|(src, dst)| format!("{} -> {}", src, dst))
+        .collect();
+    bail!(
+        "conflicting mappings for the same key, only one can apply: {}",
+        conflicts.join(", ")
+    )
+}
+
+/// Returns the source, current destination, and new destination usage for
+/// each mapping that changes the destination of a currently mapped key.
+fn find_overrides(current: &[(u64, u64)], mappings: &[Map]) -> Result<Vec<(u64, u64, u64)>> {
+    let mut overrides = Vec::new();
+    for Map(src, dst) in mappings {
+        let (src, new) = (hid::usage(src)?, hid::usage(dst)?);
+        if let Some(&(_, old)) = current.iter().find(|&&(s, d)| s == src && d != new) {
+            overrides.push((src, old, new));
+        }
+    }
+    Ok(overrides)
+}
+
+/// The modifier keys, including caps lock and fn.
+const MODIFIERS: [Key; 10] = [
+    Key::LeftControl,
+    Key::LeftShift,
+    Key::LeftOption,
+    Key::LeftCommand,
+    Key::RightControl,
+    Key::RightShift,
+    Key::RightOption,
+    Key::RightCommand,
+    Key::CapsLock,
+    Key::Fn,
+];
+
+/// Returns a note if any of the mappings remap a modifier key that can also be
+/// remapped in the macOS keyboard settings.
+fn modifier_note(mappings: &[Map]) -> Option<String> {
+    // shift can't be remapped in the settings
+    let is_modifier =
+        |key: &Key| MODIFIERS.contains(key) && !matches!(key, Key::LeftShift | Key::RightShift);
+    let mut names: Vec<_> = Vec::new();
+    for Map(src, _) in mappings {
+        if is_modifier(src) && !names.contains(&src.code()) {
+            names.push(src.code());
+        }
+    }
+    if names.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "note: remapping {}, the modifier keys can also be remapped in System Settings > \
+         Keyboard > Keyboard Shortcuts > Modifier Keys and those remaps apply on top of \
+         these ones",
+        names.join(", ")
+    ))
+}
+
+/// Returns what each modifier key produces after the mappings. A later mapping
+/// of the same key replaces an earlier one.
+fn effective_modifiers(mappings: &[Map]) -> Vec<(Key, Key)> {
+    MODIFIERS
+        .iter()
+        .map(
+            |&key| match mappings.iter().rev().find(|Map(src, _)| *src == key) {
+                Some(&Map(_, dst)) => (key, dst),
+                None => (key, key),
+            },
+        )
+        .collect()
+}
+
+/// Render a table of what each modifier key produces after the mappings.
+fn render_preview(mappings: &[Map]) -> String {
+    let effective = effective_modifiers(mappings);
+    if effective.iter().all(|(key, dst)| key == dst) {
+        return String::from("No modifier keys are remapped\n");
+    }
+    let mut s = String::from("Modifier      Produces\n");
+    for (key, dst) in effective {
+        writeln!(s, "{:<13} {}", key.to_string(), dst).unwrap();
+    }
+    s
+}
+
+/// Returns a warning if the device has a zero vendor and product ID, which
+/// `hidutil` matches broadly so the modification may apply to other keyboards.
+fn broad_match_warning(device: &Option<Device>) -> Option<String> {
+    let d = device.as_ref()?;
+    if d.vendor_id != 0 || d.product_id != 0 || d.location_id.is_some() || d.registry_id.is_some() {
+        return None;
+    }
+    Some(format!(
+        "warning: `{}` has vendor ID 0x0 and product ID 0x0, this match may be too broad and \
+         the modification could affect multiple keyboards, use `--location-id` to select \
+         a single keyboard",
+        d.name
+    ))
+}
+
+/// Returns each device with a mapping for the source usage along with the
+/// destination usage it is mapped to.
+fn find_mapped(states: &[(Device, Vec<(u64, u64)>)], src: u64) -> Vec<(&Device, u64)> {
+    states
+        .iter()
+        .flat_map(|(d, mappings)| {
+            mappings
+                .iter()
+                .filter(move |(s, _)| *s == src)
+                .map(move |&(_, dst)| (d, dst))
+        })
+        .collect()
+}
+
+fn apply(opt: &Opt, list: impl FnOnce() -> Result<Vec<Device>>) -> Result<()> {
+    let mappings = opt.mappings();
+    check_conflicts(&mappings)?;
+    let (d, devices) = select(opt, list)?;
+
+    // a global modification affects every device
+    check_max_matches(if d.is_some() { 1 } else { devices.len() }, opt.max_matches)?;
+
+    if !opt.dump && !opt.quiet {
+        if let Some(warning) = broad_match_warning(&d) {
+            eprintln!("{}", warning);
+        }
+    }
+
+    if opt.dump {
+        if opt.reset.is_some() {
+            println!("{}", dump(opt, &d, &reset_mappings(opt, &d)?)?);
+        } else if !mappings.is_empty() {
+            println!("{}", dump(opt, &d, &mappings)?);
+        }
+    } else if opt.json {
+        // a prompt would mix with the JSON output, so `--yes` is needed instead
+        if let Some(summary) = modify_summary(opt, &d, &devices, &mappings)? {
+            if !opt.quiet || !opt.yes {
+                eprintln!("{}", summary);
+            }
+            if !opt.yes {
+                bail!("aborted, use `--yes` to confirm the modification with `--json`")
+            }
+        }
+        if opt.reset.is_some() {
+            let applied = reset_mappings(opt, &d)?;
+            run_apply(opt, &d, &applied)?;
+            println!("{}", apply_json(&d, &applied)?);
+        } else {
+            if !mappings.is_empty() {
+                warn_overrides(opt, &d, &mappings)?;
+                run_apply(opt, &d, &mappings)?;
+            }
+            println!("{}", apply_json(&d, &mappings)?);
+        }
+    } else {
+        if let Some(d) = d.as_ref().filter(|_| !opt.quiet) {
+            println!(
+                "Selected:\n  Vendor ID: 0x{:x}\n  Product ID: 0x{:x}",
+                d.vendor_id, d.product_id
+            );
+            if !d.name.is_empty() {
+                println!("  Name: {}", d.name);
+            }
+            println!();
+        }
+
+        let summary = modify_summary(opt, &d, &devices, &mappings)?;
+        let interactive = io::stdin().is_terminal();
+        if let Some(summary) = summary {
+            let ask = needs_confirm(interactive, opt.yes);
+            if ask || !opt.quiet {
+                println!("{}", summary);
+            }
+            if ask && !confirm("Continue?")? {
+                bail!("aborted, use `--yes` to skip the confirmation")
+            }
+        } else if opt.reset.is_none() && mappings.len() > opt.confirm_count {
+            let ask = needs_count_confirm(mappings.len(), opt.confirm_count, interactive, opt.yes);
+            if ask || !opt.quiet {
+                println!("{}", apply_count_message(mappings.len(), &d));
+            }
+            if ask && !confirm("Continue?")? {
+                bail!("aborted, use `--yes` to skip the confirmation")
+            }
+        }
+
+        if opt.reset.is_some() {
+            run_apply(opt, &d, &reset_mappings(opt, &d)?)?;
+            if !opt.quiet {
+                println!("{}", reset_message(opt.reset_keys()));
+            }
+        } else if !mappings.is_empty() {
+            warn_overrides(opt, &d, &mappings)?;
+            if let Some(note) = modifier_note(&mappings).filter(|_| !opt.quiet) {
+                eprintln!("{}", note);
+            }
+            run_apply(opt, &d, &mappings)?;
+            if opt.quiet {
+                return Ok(());
+            }
+            println!("Applied the following modifications:");
+            for (Map(src, dst), origin) in opt.mappings_with_origin() {
+                let (src, dst) = (src.named(), dst.named());
+                match origin {
+                    Origin::Swap => println!("  {} -> {} (swap)", src, dst),
+                    Origin::Map => println!("  {} -> {}", src, dst),
+                }
+            }
+        } else if !opt.quiet {
+            println!("No modifications to apply");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the summary to show before a modification that needs confirming,
+/// which is a reset or a global modification.
+fn modify_summary(
+    opt: &Opt,
+    d: &Option<Device>,
+    devices: &[Device],
+    mappings: &[Map],
+) -> Result<Option<String>> {
+    if opt.reset.is_some() {
+        // show what will be cleared on each affected device
+        let srcs = opt.reset_usages()?;
+        let mut states = Vec::new();
+        for d in d.iter().chain(devices) {
+            // the summary is informational so failing to read it for one
+            // device should not stop the reset
+            let mappings = match hid::current(&opt.hidutil_path, &Some(d.clone())) {
+                Ok(mut mappings) => {
+                    mappings.retain(|&(src, _)| is_reset(&srcs, src));
+                    Some(mappings)
+                }
+                Err(err) => {
+                    eprintln!("warning: {:#}", err);
+                    None
+                }
+            };
+            states.push((d.clone(), mappings));
+        }
+        Ok(Some(reset_summary(&states)))
+    } else if d.is_none() && !mappings.is_empty() {
+        Ok(Some(affected_summary(devices)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the defaults from the config file.
+///
+/// The defaults are only a convenience, so unless the config file was given
+/// explicitly with `--config` failing to find or read it is just a warning.
+fn config_defaults(config: Result<Config>, explicit: bool, quiet: bool) -> Result<Defaults> {
+    match config {
+        Ok(config) => Ok(config.defaults),
+        Err(err) if !explicit => {
+            if !quiet {
+                eprintln!("warning: ignoring the config file: {:#}", err);
+            }
+            Ok(Defaults::default())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether to color the output.
+///
+/// Color is only used when writing to a terminal, and never if `--no-color`
+/// was given or the `NO_COLOR` environment variable is set.
+fn use_color(opt: &Opt) -> bool {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    !opt.no_color && !no_color && io::stdout().is_terminal()
+}
+
+/// Apply the modifications, printing the `hidutil` command first if
+/// `--verbose` was given.
+fn run_apply(opt: &Opt, device: &Option<Device>, mappings: &[Map]) -> Result<()> {
+    if opt.verbose {
+        eprintln!(
+            "Running {}",
+            hid::dump(&opt.hidutil_path, device, mappings)?
+        );
+    }
+    hid::apply(&opt.hidutil_path, device, mappings)
+}
+
+/// Apply the mappings each time the selected keyboard is connected.
+fn watch(opt: &Opt) -> Result<()> {
+    if !opt.has_selector() {
+        bail!("`--watch` requires an option selecting the keyboard, e.g. `--name`")
+    }
+    let mappings = opt.mappings();
+    if mappings.is_empty() {
+        bail!("no modifications to apply, use `--swap` or `--map`")
+    }
+
+    let wake = wake::listen();
+    let mut event = WatchEvent::Poll;
+    let mut connected = false;
+    let mut waiting = false;
+    let mut name = String::from("the keyboard");
+    loop {
+        let devices = opt.devices()?;
+        let found = is_connected(opt, &devices);
+        if should_reapply(event, connected, found) {
+            let (d, _) = select(opt, || Ok(devices))?;
+            if let Some(d) = d.as_ref().filter(|d| !d.name.is_empty()) {
+                name = format!("\"{}\"", d.name);
+            }
+            match hid::apply_if_changed(&opt.hidutil_path, &d, &mappings) {
+                Ok(applied) => {
+                    if !opt.quiet {
+                        let line = watch_applied_line(
+                            SystemTime::now(),
+                            if connected { "woke" } else { "attached" },
+                            &name,
+                            mappings.len(),
+                            applied,
+                        );
+                        println!("{}", line);
+                    }
+                    connected = true;
+                    waiting = false;
+                }
+                // the keyboard may have been disconnected again, so just try
+                // again on the next poll
+                Err(err) => eprintln!("warning: {:#}", err),
+            }
+        } else if !found && !waiting {
+            if !opt.quiet {
+                let message = if connected {
+                    format!("detached {}, waiting for it to reconnect", name)
+                } else {
+                    format!("waiting for {} to connect", name)
+                };
+                println!("{} {}", format_utc(SystemTime::now()), message);
+            }
+            connected = false;
+            waiting = true;
+        }
+        event = next_event(&wake);
+    }
+}
+
+/// Format the log line for when the mappings were checked after a keyboard
+/// was attached or the system woke, e.g.
+/// `2024-05-01T12:34:56Z attached "USB Keyboard", applied 6 modification(s)`.
+fn watch_applied_line(
+    time: SystemTime,
+    event: &str,
+    name: &str,
+    count: usize,
+    applied: bool,
+) -> String {
+    let result = if applied {
+        "applied"
+    } else {
+        "already applied"
+    };
+    format!(
+        "{} {} {}, {} {} modification(s)",
+        format_utc(time),
+        event,
+        name,
+        result,
+        count
+    )
+}
+
+/// Format the time as an ISO 8601 timestamp in UTC, e.g.
+/// `2024-05-01T12:34:56Z`.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// What caused the keyboards to be checked again when watching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchEvent {
+    /// The poll interval elapsed.
+    Poll,
+    /// The system woke from sleep, which can clear the modifications.
+    Wake,
+}
+
+/// Whether the mappings should be applied again, either because the keyboard
+/// was just connected or because the system woke while it was connected.
+fn should_reapply(event: WatchEvent, connected: bool, found: bool) -> bool {
+    found && (!connected || event == WatchEvent::Wake)
+}
+
+/// Wait for the poll interval to elapse, returning early if the system wakes.
+fn next_event(wake: &Option<Receiver<()>>) -> WatchEvent {
+    if let Some(rx) = wake {
+        match rx.recv_timeout(WATCH_INTERVAL) {
+            Ok(()) => return WatchEvent::Wake,
+            Err(RecvTimeoutError::Timeout) => return WatchEvent::Poll,
+            Err(RecvTimeoutError::Disconnected) => {}
+        }
+    }
+    thread::sleep(WATCH_INTERVAL);
+    WatchEvent::Poll
+}
+
+/// Whether any of the listed devices match the selection options.
+fn is_connected(opt: &Opt, devices: &[Device]) -> bool {
+    opt.selector()
+        .filter(devices.to_vec())
+        .is_ok_and(|d| !d.is_empty())
+}
+
+/// Select the device to modify, or `None` to modify every device.
+///
+/// Also returns the listed devices when none is selected. A registry ID selects
+/// its service directly. Otherwise if both a vendor and product ID are given
+/// without a name then the devices are not listed at all and the device is
+/// targeted directly using the IDs.
+fn select(
+    opt: &Opt,
+    list: impl FnOnce() -> Result<Vec<Device>>,
+) -> Result<(Option<Device>, Vec<Device>)> {
+    // the registry ID identifies a single service so it takes precedence
+    if let Some(Hex(registry_id)) = opt.registry_id {
+        let d = hid::find_service(&opt.list_raw()?, registry_id)?;
+        return Ok((Some(d), Vec::new()));
+    }
+
+    if let ([], None, Some(Hex(vendor_id)), [Hex(product_id)]) = (
+        opt.name.as_slice(),
+        &opt.name_fuzzy,
+        opt.vendor_id,
+        opt.product_id.as_slice(),
+    ) {
+        let d = Device::new(vendor_id, *product_id, "")
+            .with_serial(opt.serial.clone())
+            .with_transport(opt.transport.clone())
+            .with_location_id(opt.location_id.map(|Hex(id)| id));
+        return Ok((Some(d), Vec::new()));
+    }
+
+    let devices = list()?;
+    let selector = opt.selector();
+    // count the devices the same way the selector does, so that identical
+    // keyboards at different locations don't look like a partial match
+    let total = match selector.location_id {
+        Some(_) => devices.len(),
+        None => selector::merge_locations(devices.clone()).len(),
+    };
+    let mut devices = selector.filter(devices)?;
+
+    if devices.len() == 1 {
+        Ok((Some(devices.remove(0)), Vec::new()))
+    } else if devices.len() != total {
+        if let Some(transports) = selector::distinct_transports(&devices) {
+            let options: Vec<_> = transports
+                .iter()
+                .map(|t| format!("`--transport {}`", t))
+                .collect();
+            let columns = [
+                Column::VendorId,
+                Column::ProductId,
+                Column::Name,
+                Column::Transport,
+            ];
+            bail!(
+                "multiple devices matching filter, use {} to select one of them:\n{}",
+                options.join(" or "),
+                tabulate(&devices, &columns)
+            )
+        }
+        bail!(
+            "multiple devices matching filter, use `--all` to modify each of them:\n{}",
+            tabulate(&devices, Column::DEFAULT)
+        )
+    } else {
+        Ok((None, devices))
+    }
+}
+
+/// Set what the function keys do when pressed together with fn.
+fn apple_fn(opt: &Opt) -> Result<()> {
+    let mappings = opt.mappings();
+    check_conflicts(&mappings)?;
+    let (d, devices) = select(opt, || opt.devices())?;
+    check_max_matches(if d.is_some() { 1 } else { devices.len() }, opt.max_matches)?;
+
+    if opt.dump {
+        println!("{}", hid::dump_apple_fn(&opt.hidutil_path, &d, &mappings)?);
+        return Ok(());
+    }
+    if d.is_none() {
+        println!("{}", affected_summary(&devices));
+        if needs_confirm(io::stdin().is_terminal(), opt.yes) && !confirm("Continue?")? {
+            bail!("aborted, use `--yes` to skip the confirmation")
+        }
+    }
+    hid::apply_apple_fn(&opt.hidutil_path, &d, &mappings)?;
+    println!("Applied the following fn key modifications:");
+    for Map(src, dst) in mappings {
+        println!("  fn+{} -> {}", src.named(), dst.named());
+    }
+    Ok(())
+}
+
+/// Summarize the devices affected by a global modification.
+fn affected_summary(devices: &[Device]) -> String {
+    format!(
+        "No keyboard selected, the following keyboards will all be affected:\n\n{}",
+        tabulate(devices, Column::DEFAULT)
+    )
+}
+
+/// A device and the mappings that a reset will clear on it, or `None` if they
+/// could not be read.
+type ResetState = (Device, Option<Vec<(u64, u64)>>);
+
+/// Summarize the mappings that a reset will clear on each device.
+///
+/// Devices whose current mappings could not be read are shown as unknown.
+fn reset_summary(states: &[ResetState]) -> String {
+    let mut s = String::from("The following modifications will be cleared:\n");
+    for (d, mappings) in states {
+        write!(
+            s,
+            "\n  {} (0x{:x}, 0x{:x})",
+            d.name, d.vendor_id, d.product_id
+        )
+        .unwrap();
+        match mappings {
+            None => s.push_str(": unknown\n"),
+            Some(mappings) if mappings.is_empty() => s.push_str(": none\n"),
+            Some(mappings) => {
+                s.push('\n');
+                for (src, dst) in mappings {
+                    writeln!(s, "    0x{:x} -> 0x{:x}", src, dst).unwrap();
+                }
+            }
+        }
+    }
+    s
+}
+
+/// Returns the mappings to set on the device to reset the keys given to
+/// `--reset`, this is no mappings if no keys were given.
+fn reset_mappings(opt: &Opt, device: &Option<Device>) -> Result<Vec<Map>> {
+    let srcs = opt.reset_usages()?;
+    if srcs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let current = hid::current(&opt.hidutil_path, device)?;
+    current
+        .into_iter()
+        .filter(|&(src, _)| !is_reset(&srcs, src))
+        .map(|(src, dst)| Ok(Map(Key::try_from(src)?, Key::try_from(dst)?)))
+        .collect()
+}
+
+/// Returns whether a reset of the given source usages clears the mapping of
+/// this source usage. Every mapping is cleared if no usages are given.
+fn is_reset(srcs: &[u64], src: u64) -> bool {
+    srcs.is_empty() || srcs.contains(&src)
+}
+
+/// Describe what a reset of the given keys did.
+fn reset_message(keys: &[Key]) -> String {
+    if keys.is_empty() {
+        return String::from("Reset all modifications");
+    }
+    let names: Vec<_> = keys.iter().map(Key::to_string).collect();
+    format!("Reset the modifications of {}", names.join(", "))
+}
+
+/// Ask the user to confirm on standard input.
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+}
+
+/// Render the devices as JSON.
+fn list_json(devices: &[Device]) -> Result<String> {
+    Ok(serde_json::to_string(devices)?)
+}
+
+/// Render the result of applying the mappings as JSON.
+fn apply_json(device: &Option<Device>, applied: &[Map]) -> Result<String> {
+    #[derive(Serialize)]
+    struct Output<'a> {
+        schema: u32,
+        device: &'a Option<Device>,
+        applied: &'a [Map],
+        status: &'static str,
+    }
+    let output = Output {
+        schema: JSON_SCHEMA,
+        device,
+        applied,
+        status: "ok",
+    };
+    Ok(serde_json::to_string(&output)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str) -> Device {
+        Device {
+            vendor_id: 0x5ac,
+            product_id: 0x342,
+            name: name.to_owned(),
+            serial: None,
+            manufacturer: None,
+            status: None,
+            location_id: None,
+            registry_id: None,
+            transport: None,
+        }
+    }
+
+    #[test]
+    fn test_list_json() {
+        let devices = [
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+        ];
+        assert_eq!(
+            list_json(&devices).unwrap(),
+            r#"[{"vendor_id":"0x5ac","product_id":"0x342","name":"Apple Internal Keyboard / Trackpad"},{"vendor_id":"0xc45","product_id":"0x7692","name":"USB Keyboard"}]"#
+        );
+        assert_eq!(list_json(&[]).unwrap(), "[]");
+        assert!(Opt::try_parse_from(["kb-remap", "--list", "--json"]).is_ok());
+    }
+
+    #[test]
+    fn test_apply_json() {
+        let mappings = [
+            Map(Key::CapsLock, Key::LeftControl),
+            Map(Key::Char('a'), Key::Raw(0x64)),
+        ];
+        assert_eq!(
+            apply_json(&Some(device("USB Keyboard")), &mappings).unwrap(),
+            r#"{"schema":1,"device":{"vendor_id":"0x5ac","product_id":"0x342","name":"USB Keyboard"},"applied":[{"src":"capslock","dst":"lcontrol"},{"src":"a","dst":"0x64"}],"status":"ok"}"#
+        );
+        assert_eq!(
+            apply_json(&None, &[]).unwrap(),
+            r#"{"schema":1,"device":null,"applied":[],"status":"ok"}"#
+        );
+    }
+
+    #[test]
+    fn test_select_ids_skips_list() {
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--vendor-id",
+            "0x5ac",
+            "--product-id",
+            "0x342",
+            "--reset",
+        ])
+        .unwrap();
+        let (d, devices) = select(&opt, || panic!("devices should not be listed")).unwrap();
+        assert_eq!(d, Some(Device::new(0x5ac, 0x342, "")));
+        assert!(devices.is_empty());
+    }
+
+    #[test]
+    fn test_select() {
+        let list = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ])
+        };
+
+        let opt = Opt::try_parse_from(["kb-remap", "--reset"]).unwrap();
+        let (d, devices) = select(&opt, list).unwrap();
+        assert_eq!(d, None);
+        assert_eq!(devices, list().unwrap());
+
+        let opt = Opt::try_parse_from(["kb-remap", "--vendor-id", "0xc45", "--reset"]).unwrap();
+        let (d, _) = select(&opt, list).unwrap();
+        assert_eq!(d, Some(Device::new(0xc45, 0x7692, "USB Keyboard")));
+
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--name",
+            "USB Keyboard",
+            "--vendor-id",
+            "0xc45",
+            "--product-id",
+            "0x7692",
+            "--reset",
+        ])
+        .unwrap();
+        let (d, _) = select(&opt, list).unwrap();
+        assert_eq!(d, Some(Device::new(0xc45, 0x7692, "USB Keyboard")));
+
+        // identical keyboards at different locations are still a global match
+        let list = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+                Device::new(0xc45, 0x7692, "USB Keyboard").with_location_id(Some(0x14100000)),
+                Device::new(0xc45, 0x7692, "USB Keyboard").with_location_id(Some(0x14200000)),
+            ])
+        };
+        let opt = Opt::try_parse_from(["kb-remap", "--map", "capslock:escape"]).unwrap();
+        let (d, devices) = select(&opt, list).unwrap();
+        assert_eq!(d, None);
+        assert_eq!(
+            devices,
+            [
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_registry_id_precedence() {
+        let path = std::env::temp_dir().join("kb-remap-test-select-registry-id.txt");
+        fs::write(
+            &path,
+            "Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Product      Built-In
+0xc45    0x7692    0x14100000 0x1       0x6   0x100000b2c USB       USB Keyboard (null)
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000c3e USB       USB Keyboard (null)
+",
+        )
+        .unwrap();
+        let mut opt = Opt::try_parse_from([
+            "kb-remap".as_ref(),
+            "--registry-id".as_ref(),
+            "0x100000c3e".as_ref(),
+            "--hidutil-output".as_ref(),
+            path.as_os_str(),
+        ])
+        .unwrap();
+        // for example given by a profile
+        opt.vendor_id = Some(Hex(0xc45));
+        opt.product_id = vec![Hex(0x7692)];
+        let result = select(&opt, || panic!("devices should not be listed"));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.unwrap().0,
+            Some(Device::new(0xc45, 0x7692, "USB Keyboard").with_registry_id(Some(0x100000c3e)))
+        );
+    }
+
+    #[test]
+    fn test_select_serial() {
+        let list = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x29c, "Magic Keyboard").with_serial(Some("a8-91".to_owned())),
+                Device::new(0x5ac, 0x29c, "Magic Keyboard").with_serial(Some("c4-02".to_owned())),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ])
+        };
+
+        let opt = Opt::try_parse_from(["kb-remap", "--serial", "c4-02", "--reset"]).unwrap();
+        let (d, _) = select(&opt, list).unwrap();
+        assert_eq!(
+            d,
+            Some(Device::new(0x5ac, 0x29c, "Magic Keyboard").with_serial(Some("c4-02".to_owned())))
+        );
+
+        let opt = Opt::try_parse_from(["kb-remap", "--serial", "nope", "--reset"]).unwrap();
+        assert_eq!(
+            select(&opt, list).unwrap_err().to_string(),
+            "failed to find device matching serial number `nope`"
+        );
+    }
+
+    #[test]
+    fn test_select_transport() {
+        let k2 = Device::new(0x5ac, 0x24f, "Keychron K2");
+        let list = || {
+            Ok(vec![
+                k2.clone()
+                    .with_location_id(Some(0x14100000))
+                    .with_transport(Some("USB".to_owned())),
+                k2.clone()
+                    .with_location_id(Some(0x3c9f1a2b))
+                    .with_transport(Some("Bluetooth".to_owned())),
+                Device::new(0xc45, 0x7692, "USB Keyboard").with_transport(Some("USB".to_owned())),
+            ])
+        };
+
+        let opt = Opt::try_parse_from(["kb-remap", "--name", "Keychron K2", "--reset"]).unwrap();
+        let err = select(&opt, list).unwrap_err().to_string();
+        assert!(
+            err.starts_with(
+                "multiple devices matching filter, use `--transport Bluetooth` or \
+                 `--transport USB` to select one of them:\n"
+            ),
+            "{}",
+            err
+        );
+        assert!(err.contains("Bluetooth") && err.contains("USB"), "{}", err);
+
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--name",
+            "Keychron K2",
+            "--transport",
+            "usb",
+            "--reset",
+        ])
+        .unwrap();
+        let (d, _) = select(&opt, list).unwrap();
+        assert_eq!(d, Some(k2.with_transport(Some("USB".to_owned()))));
+    }
+
+    #[test]
+    fn test_current_summary() {
+        assert_eq!(
+            current_summary(&[(0x700000039, 0x7000000e0), (0x700000064, 0xc000000b8)]),
+            "\
+The following modifications are applied:
+  CapsLock -> LeftControl
+  0x64 -> 0xc000000b8
+"
+        );
+        assert_eq!(current_summary(&[]), "No modifications applied\n");
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let current = [(0x700000039, 0x7000000e0), (0x700000004, 0x700000005)];
+        let desired = [(0x700000029, 0x700000039), (0x700000039, 0x7000000e0)];
+        assert_eq!(
+            render_diff(&current, &desired, false),
+            "  CapsLock -> LeftControl\n- a -> b\n+ Escape -> CapsLock\n"
+        );
+        assert_eq!(
+            render_diff(&current, &desired, true),
+            "  CapsLock -> LeftControl\n\x1b[31m- a -> b\x1b[0m\n\x1b[32m+ Escape -> CapsLock\x1b[0m\n"
+        );
+        let reordered = [current[1], current[0]];
+        assert_eq!(
+            render_diff(&current, &reordered, false),
+            "  CapsLock -> LeftControl\n  a -> b\nNo changes\n"
+        );
+        assert_eq!(render_diff(&[], &[], false), "No changes\n");
+    }
+
+    #[test]
+    fn test_explanation() {
+        let output = "\
+Services:
+VendorID ProductID LocationID UsagePage Usage RegistryID  Transport Class
+0xc45    0x7692    0x14200000 0x1       0x6   0x100000b2c USB       AppleUserHIDEventService
+
+Devices:
+VendorID ProductID Product      Built-In
+0xc45    0x7692    USB Keyboard (null)
+";
+        let d = Device::new(0xc45, 0x7692, "USB Keyboard").with_serial(Some("a8-91".to_owned()));
+        let fields = hid::parse_fields(output, &d);
+        assert_eq!(
+            explanation(
+                &d,
+                &fields,
+                &[(0x700000039, 0x7000000e0), (0x700000004, 0x7000000b8)]
+            ),
+            "\
+Device:
+  Vendor ID: 0xc45
+  Product ID: 0x7692
+  Name: USB Keyboard
+  Serial Number: a8-91
+
+hidutil list entry 1:
+  VendorID: 0xc45
+  ProductID: 0x7692
+  Product: USB Keyboard
+  Built-In: (null)
+  LocationID: 0x14200000
+  UsagePage: 0x1
+  Usage: 0x6
+  RegistryID: 0x100000b2c
+  Transport: USB
+  Class: AppleUserHIDEventService
+
+Current modifications:
+  CapsLock -> LeftControl
+  a -> 0xb8
+"
+        );
+        assert!(explanation(&d, &[], &[]).ends_with("Current modifications:\n  none\n"));
+    }
+
+    #[test]
+    fn test_reset_keys() {
+        let opt = Opt::try_parse_from(["kb-remap", "--reset", "--all-devices"]).unwrap();
+        assert_eq!(opt.reset_keys(), []);
+        let opt = Opt::try_parse_from(["kb-remap", "--reset", "capslock", "a"]).unwrap();
+        assert_eq!(opt.reset_keys(), [Key::CapsLock, Key::Char('a')]);
+
+        let srcs = opt.reset_usages().unwrap();
+        assert_eq!(srcs, [0x700000039, 0x700000004]);
+        assert!(is_reset(&srcs, 0x700000039));
+        assert!(!is_reset(&srcs, 0x700000005));
+        assert!(is_reset(&[], 0x700000005));
+        assert_eq!(
+            reset_message(&[Key::CapsLock]),
+            "Reset the modifications of CapsLock"
+        );
+        assert_eq!(reset_message(&[]), "Reset all modifications");
+    }
+
+    /// Writes a fake `hidutil` that appends its arguments to the returned log
+    /// file and prints the given output for `--get`.
+    #[cfg(unix)]
+    fn fake_hidutil(name: &str, get: &str) -> (PathBuf, PathBuf) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("kb-remap-test-{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        let hidutil = dir.join("hidutil");
+        let log = dir.join("log");
+        let _ = fs::remove_file(&log);
+        fs::write(
+            &hidutil,
+            format!(
+                "#!/bin/sh\necho \"$@\" >> '{}'\ncase \"$*\" in *--get*) echo '{}' ;; esac\n",
+                log.display(),
+                get
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&hidutil, fs::Permissions::from_mode(0o755)).unwrap();
+        (hidutil, log)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_group() {
+        let (hidutil, log) = fake_hidutil("group", "(null)");
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--group",
+            "all-externals",
+            "--swap",
+            "capslock:escape",
+            "--quiet",
+            "--hidutil-path",
+            hidutil.to_str().unwrap(),
+        ])
+        .unwrap();
+        let config = Config::parse(
+            r#"
+[alias.k2]
+name = "Keychron K2"
+
+[alias.hhkb]
+vendor_id = "0x4fe"
+
+[group]
+all-externals = ["@k2", "@hhkb"]
+"#,
+        )
+        .unwrap();
+        let devices = vec![
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0x5ac, 0x24f, "Keychron K2"),
+            Device::new(0x4fe, 0x21, "HHKB Professional"),
+        ];
+        apply_group(&opt, &config.group("all-externals").unwrap(), || {
+            Ok(devices)
+        })
+        .unwrap();
+
+        let log = fs::read_to_string(&log).unwrap();
+        let sets: Vec<_> = log.lines().filter(|l| l.contains("--set")).collect();
+        assert_eq!(sets.len(), 2, "{}", log);
+        for (set, id) in sets.iter().zip(["0x024f", "0x0021"]) {
+            assert!(set.contains(&format!("\"ProductID\": {}", id)), "{}", set);
+            assert!(set.contains("0x700000039"), "{}", set);
+        }
+
+        let err = Opt::try_parse_from(["kb-remap", "--group", "all", "--name", "USB Keyboard"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reset_all() {
+        // capslock -> escape and a -> b
+        let (hidutil, log) = fake_hidutil(
+            "reset-all",
+            "(\n    {\n        HIDKeyboardModifierMappingDst = 30064771113;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771129;\n    },\n    {\n        \
+             HIDKeyboardModifierMappingDst = 30064771077;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771076;\n    }\n)",
+        );
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--reset",
+            "capslock",
+            "--all-devices",
+            "--quiet",
+            "--hidutil-path",
+            hidutil.to_str().unwrap(),
+        ])
+        .unwrap();
+        let devices = vec![
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+        ];
+        reset_all(&opt, || Ok(devices)).unwrap();
+
+        let log = fs::read_to_string(&log).unwrap();
+        let sets: Vec<_> = log.lines().filter(|l| l.contains("--set")).collect();
+        assert_eq!(sets.len(), 2, "{}", log);
+        for (set, id) in sets.iter().zip(["0x0342", "0x7692"]) {
+            assert!(set.contains(&format!("\"ProductID\": {}", id)), "{}", set);
+            // only the capslock modification is reset
+            assert!(
+                set.contains("0x700000004") && !set.contains("0x700000039"),
+                "{}",
+                set
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply_json_needs_yes() {
+        // capslock -> escape
+        let (hidutil, log) = fake_hidutil(
+            "apply-json",
+            "(\n    {\n        HIDKeyboardModifierMappingDst = 30064771113;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771129;\n    }\n)",
+        );
+        let opt = |args: &[&str]| {
+            let mut argv = vec!["kb-remap", "--json", "--hidutil-path"];
+            argv.push(hidutil.to_str().unwrap());
+            argv.extend(args);
+            Opt::try_parse_from(argv).unwrap()
+        };
+        let devices = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+            ])
+        };
+        let sets = || {
+            let log = fs::read_to_string(&log).unwrap_or_default();
+            log.lines().filter(|l| l.contains("--set")).count()
+        };
+
+        for args in [
+            &["--map", "a:b"][..],
+            &["--reset"],
+            &["--reset", "capslock"],
+        ] {
+            let _ = fs::remove_file(&log);
+            let err = apply(&opt(args), devices).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "aborted, use `--yes` to confirm the modification with `--json`"
+            );
+            assert_eq!(sets(), 0, "{:?}", args);
+        }
+
+        let _ = fs::remove_file(&log);
+        apply(&opt(&["--map", "a:b", "--yes"]), devices).unwrap();
+        assert_eq!(sets(), 1);
+
+        let _ = fs::remove_file(&log);
+        apply(
+            &opt(&["--name", "USB Keyboard", "--reset", "--yes"]),
+            devices,
+        )
+        .unwrap();
+        assert_eq!(sets(), 1);
+
+        // a single keyboard is modified without confirmation
+        let _ = fs::remove_file(&log);
+        apply(&opt(&["--name", "USB Keyboard", "--map", "a:b"]), devices).unwrap();
+        assert_eq!(sets(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compare() {
+        // capslock -> escape
+        let (hidutil, _) = fake_hidutil(
+            "compare",
+            "(\n    {\n        HIDKeyboardModifierMappingDst = 30064771113;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771129;\n    }\n)",
+        );
+        let snapshot = hidutil.with_file_name("snapshot.json");
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--compare",
+            snapshot.to_str().unwrap(),
+            "--quiet",
+            "--hidutil-path",
+            hidutil.to_str().unwrap(),
+        ])
+        .unwrap();
+        let devices = || Ok(vec![Device::new(0xc45, 0x7692, "USB Keyboard")]);
+        let snapshot_with = |mappings| {
+            let bundle = Bundle {
+                config: None,
+                devices: vec![DeviceMappings {
+                    vendor_id: 0xc45,
+                    product_id: 0x7692,
+                    name: "USB Keyboard".to_owned(),
+                    serial: None,
+                    mappings,
+                }],
+            };
+            bundle.write(&snapshot).unwrap();
+        };
+
+        snapshot_with(vec![Map(Key::CapsLock, Key::Escape)]);
+        compare(&opt, &snapshot, devices).unwrap();
+
+        snapshot_with(vec![Map(Key::CapsLock, Key::LeftControl)]);
+        assert_eq!(
+            compare(&opt, &snapshot, devices).unwrap_err().to_string(),
+            format!(
+                "the modifications of 1 keyboard(s) differ from `{}`",
+                snapshot.display()
+            )
+        );
+
+        // modifications that are not in the snapshot are drift too
+        snapshot_with(vec![]);
+        assert!(compare(&opt, &snapshot, devices).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dry_run_modes() {
+        // capslock -> escape
+        let (hidutil, log) = fake_hidutil(
+            "dry-run",
+            "(\n    {\n        HIDKeyboardModifierMappingDst = 30064771113;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771129;\n    }\n)",
+        );
+        let output = env::temp_dir().join("kb-remap-test-dry-run.txt");
+        fs::write(
+            &output,
+            "Devices:\nVendorID ProductID Product\n0xc45     0x7692    USB Keyboard\n",
+        )
+        .unwrap();
+        let opt = |args: &[&str]| {
+            let mut argv = vec!["kb-remap", "--dry-run", "--hidutil-path"];
+            argv.push(hidutil.to_str().unwrap());
+            argv.extend(["--hidutil-output", output.to_str().unwrap()]);
+            argv.extend(args);
+            Opt::try_parse_from(argv).unwrap()
+        };
+
+        let modes: [(&str, &dyn Fn() -> Result<()>); 6] = [
+            ("apply", &|| {
+                let opt = opt(&["--name", "USB Keyboard", "--map", "a:b"]);
+                apply(&opt, || opt.devices())
+            }),
+            ("global apply", &|| {
+                let opt = opt(&["--map", "a:b"]);
+                apply(&opt, || opt.devices())
+            }),
+            ("reset", &|| {
+                let opt = opt(&["--name", "USB Keyboard", "--reset"]);
+                apply(&opt, || opt.devices())
+            }),
+            ("reset all devices", &|| {
+                let opt = opt(&["--reset", "capslock", "--all-devices"]);
+                reset_all(&opt, || opt.devices())
+            }),
+            ("apply each", &|| {
+                apply_each(&opt(&["--all", "--map", "a:b"]))
+            }),
+            ("apple fn", &|| {
+                apple_fn(&opt(&["--apple-fn", "--map", "f1:f2"]))
+            }),
+        ];
+        for (mode, run) in modes {
+            let _ = fs::remove_file(&log);
+            run().unwrap();
+            let log = fs::read_to_string(&log).unwrap_or_default();
+            assert!(!log.contains("--set"), "{}: {}", mode, log);
+        }
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_toggle_index() {
+        let coding = [
+            Map(Key::CapsLock, Key::Escape),
+            Map(Key::Escape, Key::CapsLock),
+        ];
+        let default = [];
+        // capslock -> escape and escape -> capslock, in the order hidutil
+        // might return them
+        let current = [(0x700000029, 0x700000039), (0x700000039, 0x700000029)];
+
+        assert_eq!(toggle_index(&current, &coding).unwrap(), 1);
+        assert_eq!(toggle_index(&[], &coding).unwrap(), 0);
+        assert_eq!(toggle_index(&current, &default).unwrap(), 0);
+        assert_eq!(toggle_index(&[], &default).unwrap(), 1);
+        // anything else applies the first profile
+        assert_eq!(toggle_index(&current[..1], &coding).unwrap(), 0);
+
+        let opt = Opt::try_parse_from(["kb-remap", "--toggle", "coding", "default"]).unwrap();
+        assert_eq!(opt.toggle, ["coding", "default"]);
+    }
+
+    #[test]
+    fn test_clear_cache() {
+        let dir = env::temp_dir().join("kb-remap-test-clear-cache-option");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hidutil-list-0.txt");
+        fs::write(&path, "Devices:\n").unwrap();
+
+        let opt = Opt::try_parse_from(["kb-remap", "--clear-cache", "--dry-run"]).unwrap();
+        clear_cache(&opt, &dir).unwrap();
+        let kept = path.exists();
+        let opt = Opt::try_parse_from(["kb-remap", "--clear-cache"]).unwrap();
+        clear_cache(&opt, &dir).unwrap();
+        let removed = !path.exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(kept);
+        assert!(removed);
+    }
+
+    #[test]
+    fn test_append_map() {
+        let dir = env::temp_dir().join("kb-remap-test-append-map");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("kb-remap").join("config.toml");
+        let run = |args: &[&str]| {
+            let mut argv = vec!["kb-remap", "--quiet", "--config", path.to_str().unwrap()];
+            argv.extend(args);
+            let opt = Opt::try_parse_from(argv).unwrap();
+            let [profile, mapping] = opt.append_map.as_slice() else {
+                panic!("expected a profile and mapping")
+            };
+            append_map(&opt, profile, mapping)
+        };
+
+        run(&["--dry-run", "--append-map", "laptop", "capslock:escape"]).unwrap();
+        assert!(!path.exists());
+        run(&["--append-map", "laptop", "capslock:escape"]).unwrap();
+        run(&["--append-map", "usb", "a:b"]).unwrap();
+        run(&["--append-map", "laptop", "lcommand:loption"]).unwrap();
+        let err = run(&["--append-map", "laptop", "capslock"]).unwrap_err();
+        let config = Config::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            config.profile("laptop").unwrap().map,
+            [
+                Mappings(vec![Map(Key::CapsLock, Key::Escape)]),
+                Mappings(vec![Map(Key::LeftCommand, Key::LeftOption)]),
+            ]
+        );
+        assert_eq!(
+            config.profile("usb").unwrap().map,
+            [Mappings(vec![Map(Key::Char('a'), Key::Char('b'))])]
+        );
+        assert!(
+            format!("{:#}", err).contains("colon not found"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_import_all() {
+        // capslock -> escape
+        let (exporter, _) = fake_hidutil(
+            "export-all",
+            "(\n    {\n        HIDKeyboardModifierMappingDst = 30064771113;\n        \
+             HIDKeyboardModifierMappingSrc = 30064771129;\n    }\n)",
+        );
+        let (importer, log) = fake_hidutil("import-all", "(null)");
+        let dir = env::temp_dir().join("kb-remap-test-export-import-all");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let config = "[profile.laptop]\nswap = [\"capslock:escape\"]\n";
+        let config_path = dir.join("config.toml");
+        fs::write(&config_path, config).unwrap();
+        let (bundle, imported) = (dir.join("bundle.json"), dir.join("imported.toml"));
+        let opt = |args: &[&Path]| {
+            let mut argv = vec![Path::new("kb-remap"), Path::new("--quiet")];
+            argv.extend(args);
+            Opt::try_parse_from(argv).unwrap()
+        };
+        let devices = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x29c, "Magic Keyboard"),
+                Device::new(0x5ac, 0x29c, "Magic Keyboard").with_location_id(Some(0x1)),
+            ])
+        };
+
+        // a dry run changes nothing
+        let export = |extra: &[&Path]| {
+            let mut args = vec![
+                Path::new("--export-all"),
+                &bundle,
+                Path::new("--config"),
+                &config_path,
+                Path::new("--hidutil-path"),
+                &exporter,
+            ];
+            args.extend(extra);
+            export_all(&opt(&args), &bundle, devices).unwrap();
+        };
+        export(&[Path::new("--dry-run")]);
+        assert!(!bundle.exists());
+        export(&[]);
+
+        let import = |extra: &[&Path]| {
+            let mut args = vec![
+                Path::new("--import-all"),
+                &bundle,
+                Path::new("--config"),
+                &imported,
+                Path::new("--hidutil-path"),
+                &importer,
+                Path::new("--yes"),
+            ];
+            args.extend(extra);
+            import_all(&opt(&args), &bundle).unwrap();
+        };
+        import(&[Path::new("--dry-run")]);
+        assert!(!imported.exists());
+        assert!(!log.exists());
+
+        import(&[]);
+        assert_eq!(fs::read_to_string(&imported).unwrap(), config);
+        let log = fs::read_to_string(&log).unwrap();
+        let sets: Vec<_> = log.lines().filter(|l| l.contains("--set")).collect();
+        assert_eq!(sets.len(), 1, "{}", log);
+        assert!(
+            sets[0].contains("\"ProductID\": 0x029c")
+                && sets[0].contains(
+                    "\"HIDKeyboardModifierMappingSrc\":0x700000039,\
+                     \"HIDKeyboardModifierMappingDst\":0x700000029"
+                ),
+            "{}",
+            sets[0]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reset_summary() {
+        let states = vec![
+            (
+                device("Apple Internal Keyboard / Trackpad"),
+                Some(vec![(0x700000039, 0x7000000e0), (0x7000000e0, 0x700000039)]),
+            ),
+            (device("USB Keyboard"), Some(vec![])),
+            (device("Magic Keyboard"), None),
+        ];
+        assert_eq!(
+            reset_summary(&states),
+            "The following modifications will be cleared:
+
+  Apple Internal Keyboard / Trackpad (0x5ac, 0x342)
+    0x700000039 -> 0x7000000e0
+    0x7000000e0 -> 0x700000039
+
+  USB Keyboard (0x5ac, 0x342): none
+
+  Magic Keyboard (0x5ac, 0x342): unknown
+"
+        );
+    }
+
+    #[test]
+    fn test_all() {
+        let opt =
+            Opt::try_parse_from(["kb-remap", "--name", "!BTM", "--map", "a:b", "--all"]).unwrap();
+        let devices = vec![
+            device("Apple Internal Keyboard / Trackpad"),
+            device("BTM"),
+            device("USB Keyboard"),
+        ];
+        assert_eq!(
+            opt.selector().filter(devices.clone()).unwrap(),
+            [devices[0].clone(), devices[2].clone()]
+        );
+        assert!(Opt::try_parse_from(["kb-remap", "--all", "--each-confirm"]).is_err());
+    }
+
+    #[test]
+    fn test_choose_devices() {
+        let devices = vec![
+            device("Apple Internal Keyboard / Trackpad"),
+            device("USB Keyboard"),
+            device("YubiKey OTP+FIDO+CCID"),
+        ];
+        let mut answers = [false, true, true].into_iter();
+        let mut asked = Vec::new();
+        let chosen = choose_devices(devices.clone(), |d| {
+            asked.push(d.name.clone());
+            Ok(answers.next().unwrap())
+        })
+        .unwrap();
+        assert_eq!(chosen, devices[1..]);
+        assert_eq!(
+            asked,
+            [
+                "Apple Internal Keyboard / Trackpad",
+                "USB Keyboard",
+                "YubiKey OTP+FIDO+CCID"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_affected_summary() {
+        let devices = [
+            device("Apple Internal Keyboard / Trackpad"),
+            device("USB Keyboard"),
+        ];
+        assert_eq!(
+            affected_summary(&devices),
+            "No keyboard selected, the following keyboards will all be affected:
+
+Vendor ID  Product ID  Name
+---------  ----------  ----------------------------------
+0x5ac      0x342       Apple Internal Keyboard / Trackpad
+0x5ac      0x342       USB Keyboard
+"
+        );
+    }
+
+    #[test]
+    fn test_affected_summary_global_apply() {
+        let list = || {
+            Ok(vec![
+                Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad"),
+                Device::new(0xc45, 0x7692, "USB Keyboard"),
+                Device::new(0x5ac, 0x29c, "Magic Keyboard"),
+            ])
+        };
+        let opt = Opt::try_parse_from(["kb-remap", "--map", "capslock:escape"]).unwrap();
+        let (d, devices) = select(&opt, list).unwrap();
+        assert_eq!(d, None);
+        let summary = affected_summary(&devices);
+        for device in list().unwrap() {
+            assert!(summary.contains(&device.name), "{} missing", device.name);
+        }
+
+        // scripts are not asked
+        assert!(needs_confirm(true, false));
+        assert!(!needs_confirm(true, true));
+        assert!(!needs_confirm(false, false));
+    }
+
+    #[test]
+    fn test_check_conflicts() {
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--map",
+            "capslock:escape",
+            "--map",
+            "a:b",
+            "--map",
+            "capslock:return",
+        ])
+        .unwrap();
+        assert_eq!(
+            check_conflicts(&opt.mappings()).unwrap_err().to_string(),
+            "conflicting mappings for the same key, only one can apply: CapsLock -> Escape, \
+             CapsLock -> Return"
+        );
+
+        // swaps have different sources and repeating a mapping is harmless
+        let opt =
+            Opt::try_parse_from(["kb-remap", "--swap", "capslock:escape", "--map", "a:b,a:b"])
+                .unwrap();
+        check_conflicts(&opt.mappings()).unwrap();
+
+        let opt =
+            Opt::try_parse_from(["kb-remap", "--swap", "capslock:escape", "--map", "escape:a"])
+                .unwrap();
+        assert!(check_conflicts(&opt.mappings()).is_err());
+    }
+
+    #[test]
+    fn test_mappings_swap_adjacent() {
+        for args in [
+            ["--swap", "a:capslock", "--map", "escape:b"],
+            ["--map", "escape:b", "--swap", "capslock:a"],
+        ] {
+            let opt =
+                Opt::try_parse_from(["kb-remap"].iter().chain(&args).chain(&["--sort-mappings"]))
+                    .unwrap();
+            assert_eq!(
+                dump(&opt, &None, &opt.mappings()).unwrap(),
+                r#"hidutil property \
+  --set '{"UserKeyMapping":[{"HIDKeyboardModifierMappingSrc":0x700000004,"HIDKeyboardModifierMappingDst":0x700000039},{"HIDKeyboardModifierMappingSrc":0x700000039,"HIDKeyboardModifierMappingDst":0x700000004},{"HIDKeyboardModifierMappingSrc":0x700000029,"HIDKeyboardModifierMappingDst":0x700000005}]}'"#,
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn test_mappings_with_origin() {
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--map",
+            "a:b",
+            "--swap",
+            "capslock:escape",
+            "--sort-mappings",
+        ])
+        .unwrap();
+        assert_eq!(
+            opt.mappings_with_origin(),
+            [
+                (Map(Key::Char('a'), Key::Char('b')), Origin::Map),
+                (Map(Key::Escape, Key::CapsLock), Origin::Swap),
+                (Map(Key::CapsLock, Key::Escape), Origin::Swap),
+            ]
+        );
+        assert_eq!(
+            opt.mappings(),
+            [
+                Map(Key::Char('a'), Key::Char('b')),
+                Map(Key::Escape, Key::CapsLock),
+                Map(Key::CapsLock, Key::Escape),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let opt = Opt::try_parse_from(["kb-remap", "--map", "a:b", "--dump"]).unwrap();
+        assert_eq!(opt.format, Format::Shell);
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--map",
+            "a:b",
+            "--dump",
+            "--format",
+            "applescript",
+        ])
+        .unwrap();
+        assert_eq!(opt.format, Format::AppleScript);
+        assert!(dump(&opt, &None, &opt.mappings())
+            .unwrap()
+            .starts_with(r#"do shell script "hidutil property --set '{\"UserKeyMapping\""#));
+        assert_eq!(
+            Format::from_str("zsh").unwrap_err().to_string(),
+            "unknown format `zsh`, expected one of `shell`, `applescript`, or `plist`"
+        );
+    }
+
+    #[test]
+    fn test_dry_run() {
+        for args in [
+            &["--map", "capslock:escape"][..],
+            &["--reset"],
+            &["--reset", "--all-devices"],
+        ] {
+            let opt =
+                Opt::try_parse_from(["kb-remap"].iter().chain(args).chain(&["--dry-run"])).unwrap();
+            assert!(opt.dump);
+        }
+        // modes that can't print what they would do reject it
+        for args in [
+            ["--edit-config"].as_slice(),
+            &["--watch", "--name", "USB Keyboard", "--map", "a:b"],
+            &["--share", "--map", "a:b"],
+        ] {
+            let argv = ["kb-remap", "--dry-run"].iter().chain(args);
+            assert!(Opt::try_parse_from(argv).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn test_share_round_trip() {
+        let opt = Opt::try_parse_from([
+            "kb-remap",
+            "--name",
+            "USB Keyboard",
+            "--product-id",
+            "0x7692",
+            "--swap",
+            "capslock:escape",
+            "--map",
+            "command:control",
+            "--share",
+        ])
+        .unwrap();
+        let token = opt.to_share().unwrap().encode().unwrap();
+
+        let mut other = Opt::try_parse_from(["kb-remap", "--from-share", &token]).unwrap();
+        other.set_share(Share::decode(other.from_share.as_ref().unwrap()).unwrap());
+        assert_eq!(other.name, ["USB Keyboard"]);
+        assert_eq!(other.name_fuzzy, None);
+        assert!(other.vendor_id.is_none());
+        assert_eq!(other.selector().product_ids, [0x7692]);
+        assert_eq!(other.mappings(), opt.mappings());
+    }
+
+    #[test]
+    fn test_render_list() {
+        let output =
+            "Devices:\nVendorID ProductID Product Built-In\n0x0      0x0       BTM     (null)\n";
+        assert_eq!(
+            render_list(output, true, false, None, Column::DEFAULT, None).unwrap(),
+            output
+        );
+        assert_eq!(
+            render_list(output, false, false, None, Column::DEFAULT, None).unwrap(),
+            tabulate(&[Device::new(0, 0, "BTM")], Column::DEFAULT)
+        );
+    }
+
+    #[test]
+    fn test_render_list_group_by_vendor() {
+        let output = "\
+Devices:
+VendorID ProductID Product                            Built-In
+0xc45    0x7692    USB Keyboard                       0
+0x5ac    0x342     Apple Internal Keyboard / Trackpad 1
+0xc45    0x7693    USB Keyboard                       0
+";
+        let apple = [Device::new(
+            0x5ac,
+            0x342,
+            "Apple Internal Keyboard / Trackpad",
+        )];
+        let other = [
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+            Device::new(0xc45, 0x7693, "USB Keyboard"),
+        ];
+        assert_eq!(
+            render_list(
+                output,
+                false,
+                false,
+                Some(GroupBy::Vendor),
+                &[Column::ProductId, Column::Name],
+                None
+            )
+            .unwrap(),
+            format!(
+                "Vendor 0x5ac:\n{}\nVendor 0xc45:\n{}",
+                tabulate(&apple, &[Column::ProductId, Column::Name]),
+                tabulate(&other, &[Column::ProductId, Column::Name])
+            )
+        );
+        assert!(Opt::try_parse_from(["kb-remap", "--list", "--group-by", "product"]).is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let err =
+            Opt::try_parse_from(["kb-remap", "--profile", "laptop", "--swap", "a:b"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        let err =
+            Opt::try_parse_from(["kb-remap", "--host-profile", "--profile", "laptop"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(!hostname().unwrap().is_empty());
+
+        let config = Config::parse(
+            "[profile.laptop]\nname = \"USB Keyboard\"\nvendor_id = \"0xc45\"\nswap = [\"a:b\"]\n",
+        )
+        .unwrap();
+        let mut opt =
+            Opt::try_parse_from(["kb-remap", "--profile", "laptop", "--name", "BTM"]).unwrap();
+        opt.set_profile(config.profile("laptop").unwrap().clone());
+        assert_eq!(opt.name, ["BTM"]);
+        assert_eq!(opt.vendor_id.map(|Hex(id)| id), Some(0xc45));
+        assert_eq!(
+            opt.mappings(),
+            [
+                Map(Key::Char('a'), Key::Char('b')),
+                Map(Key::Char('b'), Key::Char('a'))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shift_row() {
+        let opt =
+            Opt::try_parse_from(["kb-remap", "--shift-row", "home", "-1", "--map", "a:b"]).unwrap();
+        assert_eq!(opt.shift_row, ["home", "-1"]);
+        assert_eq!(
+            shift_row("home", "-1").unwrap().0[..2],
+            [
+                Map(Key::Char('a'), Key::Char('\'')),
+                Map(Key::Char('s'), Key::Char('a'))
+            ]
+        );
+        assert_eq!(
+            shift_row("home", "one").unwrap_err().to_string(),
+            "failed to parse `one` as a number of keys"
+        );
+    }
+
+    #[test]
+    fn test_is_connected() {
+        let opt = Opt::try_parse_from(["kb-remap", "--name", "USB Keyboard", "--watch"]).unwrap();
+        assert!(is_connected(&opt, &[device("BTM"), device("USB Keyboard")]));
+        assert!(!is_connected(&opt, &[device("BTM")]));
+        assert!(!is_connected(&opt, &[]));
+    }
+
+    #[test]
+    fn test_effective_modifiers() {
+        let opt = Opt::try_parse_from(["kb-remap", "--swap", "control:command"]).unwrap();
+        let effective = effective_modifiers(&opt.mappings());
+        for (key, dst) in [
+            (Key::LeftControl, Key::LeftCommand),
+            (Key::RightControl, Key::RightCommand),
+            (Key::LeftCommand, Key::LeftControl),
+            (Key::RightCommand, Key::RightControl),
+            (Key::LeftShift, Key::LeftShift),
+        ] {
+            assert!(effective.contains(&(key, dst)), "{} -> {}", key, dst);
+        }
+
+        // a later mapping replaces an earlier one
+        let mappings = [
+            Map(Key::CapsLock, Key::Escape),
+            Map(Key::CapsLock, Key::LeftControl),
+        ];
+        assert!(effective_modifiers(&mappings).contains(&(Key::CapsLock, Key::LeftControl)));
+        assert_eq!(
+            render_preview(&[Map(Key::Char('a'), Key::Char('b'))]),
+            "No modifier keys are remapped\n"
+        );
+    }
+
+    #[test]
+    fn test_set_defaults() {
+        let defaults = Defaults {
+            json: Some(true),
+            quiet: None,
+            verbose: None,
+            no_color: None,
+            name: Some("USB Keyboard".to_owned()),
+            vendor_id: None,
+            product_id: None,
+        };
+
+        let mut opt = Opt::try_parse_from(["kb-remap", "--map", "a:b"]).unwrap();
+        opt.set_defaults(defaults.clone());
+        assert!(opt.json);
+        assert_eq!(opt.name, ["USB Keyboard"]);
+
+        // options on the command line take precedence
+        let mut opt = Opt::try_parse_from([
+            "kb-remap",
+            "--no-json",
+            "--vendor-id",
+            "0xc45",
+            "--map",
+            "a:b",
+        ])
+        .unwrap();
+        opt.set_defaults(defaults.clone());
+        assert!(!opt.json);
+        assert!(opt.name.is_empty());
+        assert_eq!(opt.vendor_id.map(|Hex(id)| id), Some(0xc45));
+
+        // listing with a raw output can't use JSON
+        let mut opt = Opt::try_parse_from(["kb-remap", "--list", "--raw"]).unwrap();
+        opt.set_defaults(defaults);
+        assert!(!opt.json);
+        assert!(opt.name.is_empty());
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config = Config::parse("[defaults]\njson = true\n");
+        assert_eq!(
+            config_defaults(config, false, false).unwrap().json,
+            Some(true)
+        );
+
+        // a malformed or missing config file only stops an explicit `--config`
+        let err = || Config::parse("[defaults]\njson = 1\n");
+        assert_eq!(
+            config_defaults(err(), false, false).unwrap(),
+            Defaults::default()
+        );
+        assert!(config_defaults(err(), true, false).is_err());
+    }
+
+    #[test]
+    fn test_set_defaults_verbosity() {
+        let defaults = Defaults {
+            quiet: Some(true),
+            no_color: Some(true),
+            ..Default::default()
+        };
+
+        let mut opt = Opt::try_parse_from(["kb-remap", "--map", "a:b"]).unwrap();
+        opt.set_defaults(defaults.clone());
+        assert!(opt.quiet);
+        assert!(!opt.verbose);
+        assert!(opt.no_color);
+
+        // options on the command line take precedence
+        let mut opt = Opt::try_parse_from(["kb-remap", "--verbose", "--map", "a:b"]).unwrap();
+        opt.set_defaults(defaults);
+        assert!(!opt.quiet);
+        assert!(opt.verbose);
+
+        assert!(Opt::try_parse_from(["kb-remap", "--quiet", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_edit_config() {
+        let dir = env::temp_dir().join("kb-remap-test-edit-config");
+        let path = dir.join("kb-remap").join("config.toml");
+        let _ = fs::remove_dir_all(&dir);
+
+        // a new config file is created from the template
+        let mut template = String::new();
+        let result = edit_config(
+            &path,
+            |p| {
+                template = fs::read_to_string(p)?;
+                Ok(())
+            },
+            |_| Ok(false),
+        );
+        assert!(result.is_ok());
+        assert_eq!(template, Config::TEMPLATE);
+
+        // a malformed config is rejected after each edit
+        let mut retries = 0;
+        let result = edit_config(
+            &path,
+            |p| Ok(fs::write(p, "[profile.laptop\n")?),
+            |_| {
+                retries += 1;
+                Ok(retries < 2)
+            },
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(retries, 2);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("failed to parse `"));
+    }
+
+    #[test]
+    fn test_completions() {
+        let opt = Opt::try_parse_from(["kb-remap", "--completions", "zsh"]).unwrap();
+        assert_eq!(opt.completions, Some(Shell::Zsh));
+        let mut script = Vec::new();
+        clap_complete::generate(Shell::Bash, &mut Opt::command(), "kb-remap", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("--name"), "{}", script);
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(time(951_868_799)), "2000-02-29T23:59:59Z");
+        assert_eq!(format_utc(time(1_714_566_896)), "2024-05-01T12:34:56Z");
+    }
+
+    #[test]
+    fn test_watch_applied_line() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_714_566_896);
+        assert_eq!(
+            watch_applied_line(time, "attached", "\"K2\"", 6, true),
+            "2024-05-01T12:34:56Z attached \"K2\", applied 6 modification(s)"
+        );
+        assert_eq!(
+            watch_applied_line(time, "woke", "\"K2\"", 1, false),
+            "2024-05-01T12:34:56Z woke \"K2\", already applied 1 modification(s)"
+        );
+    }
+
+    #[test]
+    fn test_should_reapply() {
+        // newly connected
+        assert!(should_reapply(WatchEvent::Poll, false, true));
+        // still connected
+        assert!(!should_reapply(WatchEvent::Poll, true, true));
+        // woke while connected
+        assert!(should_reapply(WatchEvent::Wake, true, true));
+        // woke while disconnected
+        assert!(!should_reapply(WatchEvent::Wake, false, false));
+    }
+
+    #[test]
+    fn test_read_mappings() {
+        let json = r#"[{"src": "capslock", "dst": "lcontrol"}, {"src": "a", "dst": "0x64"}]"#;
+        assert_eq!(
+            read_mappings(json.as_bytes()).unwrap(),
+            Mappings(vec![
+                Map(Key::CapsLock, Key::LeftControl),
+                Map(Key::Char('a'), Key::Raw(0x64)),
+            ])
+        );
+        let err = read_mappings(r#"[{"src": "capslock", "dst": "nope"}]"#.as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "failed to parse mappings from stdin");
+    }
+
+    #[test]
+    fn test_modify_each_continue_on_error() {
+        let devices = ["a", "b", "c"];
+        let modify = |applied: &mut Vec<&str>, d| {
+            if d == "b" {
+                bail!("failed to modify {}", d);
+            }
+            applied.push(d);
+            Ok(())
+        };
+
+        let opt = |args: &[&str]| {
+            let mut argv = vec!["kb-remap", "--all", "--map", "a:b"];
+            argv.extend(args);
+            Opt::try_parse_from(argv).unwrap()
+        };
+
+        // the summary is not printed with `--quiet`
+        for args in [
+            &["--continue-on-error"][..],
+            &["--continue-on-error", "--quiet"],
+        ] {
+            let mut applied = Vec::new();
+            let err = modify_each(&opt(args), devices, |d| modify(&mut applied, d)).unwrap_err();
+            assert_eq!(err.to_string(), "failed to modify 1 of 3 keyboard(s)");
+            assert_eq!(applied, ["a", "c"]);
+        }
+
+        let mut applied = Vec::new();
+        let err = modify_each(&opt(&[]), devices, |d| modify(&mut applied, d)).unwrap_err();
+        assert_eq!(err.to_string(), "failed to modify b");
+        assert_eq!(applied, ["a"]);
+    }
+
+    #[test]
+    fn test_broad_match_warning() {
+        let d = Device::new(0x0, 0x0, "Apple Internal Keyboard / Trackpad");
+        assert_eq!(
+            broad_match_warning(&Some(d.clone())).unwrap(),
+            "warning: `Apple Internal Keyboard / Trackpad` has vendor ID 0x0 and product ID \
+             0x0, this match may be too broad and the modification could affect multiple \
+             keyboards, use `--location-id` to select a single keyboard"
+        );
+        assert_eq!(
+            broad_match_warning(&Some(d.with_location_id(Some(0x14100000)))),
+            None
+        );
+        assert_eq!(broad_match_warning(&Some(device("USB Keyboard"))), None);
+        assert_eq!(broad_match_warning(&None), None);
+    }
+
+    #[test]
+    fn test_filter_location_id() {
+        let devices = vec![
+            device("USB Keyboard").with_location_id(Some(0x14100000)),
+            device("USB Keyboard").with_location_id(Some(0x14200000)),
+        ];
+        let opt = Opt::try_parse_from(["kb-remap", "--location-id", "0x14200000"]).unwrap();
+        assert_eq!(
+            opt.selector().filter(devices.clone()).unwrap(),
+            [devices[1].clone()]
+        );
+        let opt = Opt::try_parse_from(["kb-remap", "--name", "USB Keyboard"]).unwrap();
+        assert_eq!(
+            opt.selector().filter(devices.clone()).unwrap(),
+            [device("USB Keyboard")]
+        );
+        let opt = Opt::try_parse_from(["kb-remap", "--location-id", "0x0"]).unwrap();
+        assert_eq!(
+            opt.selector().filter(devices).unwrap_err().to_string(),
+            "failed to find device matching location id `0x0`"
+        );
+    }
+
+    #[test]
+    fn test_hidutil_path() {
+        let opt = Opt::try_parse_from(["kb-remap", "--list"]).unwrap();
+        assert_eq!(opt.hidutil_path, PathBuf::from("hidutil"));
+        let opt = Opt::try_parse_from(["kb-remap", "--list", "--hidutil-path", "/opt/bin/hidutil"])
+            .unwrap();
+        assert_eq!(opt.hidutil_path, PathBuf::from("/opt/bin/hidutil"));
+    }
+
+    #[test]
+    fn test_hidutil_output() {
+        let path = std::env::temp_dir().join("kb-remap-test-hidutil-output.txt");
+        fs::write(
+            &path,
+            "Devices:
+VendorID ProductID Product                            Built-In
+0x5ac    0x342     Apple Internal Keyboard / Trackpad 1
+0xc45    0x7692    USB Keyboard                       (null)
+",
+        )
+        .unwrap();
+        let opt = Opt::try_parse_from([
+            "kb-remap".as_ref(),
+            "--list".as_ref(),
+            "--hidutil-output".as_ref(),
+            path.as_os_str(),
+        ])
+        .unwrap();
+        let output = render_list(
+            &opt.list_raw().unwrap(),
+            opt.raw,
+            opt.group_by_id,
+            opt.group_by,
+            Column::DEFAULT,
+            None,
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            output,
+            "\
+Vendor ID  Product ID  Name
+---------  ----------  ----------------------------------
+0x5ac      0x342       Apple Internal Keyboard / Trackpad
+0xc45      0x7692      USB Keyboard
+"
+        );
+    }
+
+    #[test]
+    fn test_check_max_matches() {
+        assert!(check_max_matches(7, None).is_ok());
+        assert!(check_max_matches(2, Some(2)).is_ok());
+        assert_eq!(
+            check_max_matches(7, Some(2)).unwrap_err().to_string(),
+            "7 devices would be affected but `--max-matches` is 2, use a filter to narrow the selection"
+        );
+    }
+
+    #[test]
+    fn test_confirm_count() {
+        let opt = Opt::try_parse_from(["kb-remap", "--map", "a:b"]).unwrap();
+        assert_eq!(opt.confirm_count, 8);
+        assert!(!needs_count_confirm(8, 8, true, false));
+        assert!(needs_count_confirm(9, 8, true, false));
+        assert!(!needs_count_confirm(9, 8, true, true));
+        assert!(!needs_count_confirm(9, 8, false, false));
+        assert_eq!(
+            apply_count_message(20, &Some(device("USB Keyboard"))),
+            "About to apply 20 mappings to USB Keyboard"
+        );
+        assert_eq!(
+            apply_count_message(20, &None),
+            "About to apply 20 mappings to every keyboard"
+        );
+    }
+
+    #[test]
+    fn test_find_overrides() {
+        let current = [(0x700000039, 0x7000000e0), (0x700000004, 0x700000005)];
+        let mappings = [
+            Map(Key::CapsLock, Key::Escape),
+            Map(Key::Char('a'), Key::Char('b')),
+            Map(Key::Fn, Key::LeftControl),
+        ];
+        let overrides = find_overrides(&current, &mappings).unwrap();
+        assert_eq!(overrides, [(0x700000039, 0x7000000e0, 0x700000029)]);
+        assert_eq!(usage_name(overrides[0].1), "LeftControl");
+        assert_eq!(usage_name(0x39), "0x39");
+    }
+
+    #[test]
+    fn test_modifier_note() {
+        let note = modifier_note(&[
+            Map(Key::Char('a'), Key::Char('b')),
+            Map(Key::CapsLock, Key::Escape),
+            Map(Key::LeftCommand, Key::LeftOption),
+            Map(Key::LeftOption, Key::LeftCommand),
+        ])
+        .unwrap();
+        assert!(note.starts_with("note: remapping capslock, lcommand, loption, "));
+        assert!(note.contains("Modifier Keys"));
+        assert_eq!(modifier_note(&[Map(Key::Escape, Key::CapsLock)]), None);
+    }
+
+    #[test]
+    fn test_find_mapped() {
+        let states = vec![
+            (device("BTM"), vec![]),
+            (
+                device("Apple Internal Keyboard / Trackpad"),
+                vec![(0x700000039, 0x7000000e0), (0x7000000e0, 0x700000039)],
+            ),
+            (device("USB Keyboard"), vec![(0x700000039, 0x700000029)]),
+        ];
+        let found = find_mapped(&states, 0x700000039);
+        assert_eq!(
+            found,
+            [(&states[1].0, 0x7000000e0), (&states[2].0, 0x700000029)]
+        );
+        assert!(find_mapped(&states, 0x700000004).is_empty());
     }
-    s
 }
@@ -0,0 +1,110 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::types::{Key, Map, Mappings};
+
+/// A row of character keys on an ANSI keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Row {
+    Number,
+    Top,
+    Home,
+    Bottom,
+}
+
+impl FromStr for Row {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let row = match s {
+            "number" => Self::Number,
+            "top" => Self::Top,
+            "home" => Self::Home,
+            "bottom" => Self::Bottom,
+            r => bail!(
+                "unknown row `{}`, expected one of `number`, `top`, `home`, or `bottom`",
+                r
+            ),
+        };
+        Ok(row)
+    }
+}
+
+impl Row {
+    /// The characters of the keys in the row, from left to right.
+    fn chars(self) -> &'static str {
+        match self {
+            Self::Number => "`1234567890-=",
+            Self::Top => "qwertyuiop[]\\",
+            Self::Home => "asdfghjkl;'",
+            Self::Bottom => "zxcvbnm,./",
+        }
+    }
+
+    /// Map each key in the row to the key `n` positions to the right of it,
+    /// wrapping around at the ends of the row. A negative `n` shifts to the
+    /// left instead.
+    pub fn shift(self, n: i64) -> Mappings {
+        let keys: Vec<_> = self.chars().chars().map(Key::Char).collect();
+        let len = keys.len() as i64;
+        let mappings = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &src)| {
+                let j = (i as i64 + n).rem_euclid(len) as usize;
+                Map(src, keys[j])
+            })
+            .filter(|Map(src, dst)| src != dst)
+            .collect();
+        Mappings(mappings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(mappings: &Mappings) -> Vec<(char, char)> {
+        mappings
+            .0
+            .iter()
+            .map(|m| match m {
+                Map(Key::Char(src), Key::Char(dst)) => (*src, *dst),
+                m => panic!("unexpected mapping {:?}", m),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn row_shift() {
+        assert_eq!(
+            chars(&Row::Bottom.shift(1)),
+            [
+                ('z', 'x'),
+                ('x', 'c'),
+                ('c', 'v'),
+                ('v', 'b'),
+                ('b', 'n'),
+                ('n', 'm'),
+                ('m', ','),
+                (',', '.'),
+                ('.', '/'),
+                ('/', 'z'),
+            ]
+        );
+        assert_eq!(
+            chars(&Row::Home.shift(-2))[..3],
+            [('a', ';'), ('s', '\''), ('d', 'a')]
+        );
+        assert_eq!(Row::Top.shift(13), Mappings(vec![]));
+    }
+
+    #[test]
+    fn row_from_str_unknown() {
+        assert_eq!(
+            Row::from_str("function").unwrap_err().to_string(),
+            "unknown row `function`, expected one of `number`, `top`, `home`, or `bottom`"
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use anyhow::{bail, Result};
+
+use crate::fuzzy;
+use crate::hid::Device;
+
+/// A selection of keyboards.
+///
+/// A keyboard is selected if it matches every field that is set, and fields
+/// that can have multiple values match if any one of the values matches. For
+/// example `--name A --name B --vendor-id 0x5ac` selects the keyboards named
+/// either `A` or `B` that also have the vendor ID `0x5ac`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    /// Names prefixed with `!` exclude the keyboards with that name instead.
+    pub names: Vec<String>,
+    pub name_fuzzy: Option<String>,
+    pub search: Option<String>,
+    pub vendor_id: Option<u64>,
+    pub product_ids: Vec<u64>,
+    pub serial: Option<String>,
+    pub transport: Option<String>,
+    pub location_id: Option<u64>,
+}
+
+impl Selector {
+    /// Whether no field is set, in which case every keyboard is selected.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Returns the devices matching the selection, failing if there are none
+    /// left after any of the fields is applied.
+    pub fn filter(&self, mut devices: Vec<Device>) -> Result<Vec<Device>> {
+        if !self.names.is_empty() {
+            retain_names(&mut devices, &self.names)?;
+        }
+
+        if let Some(query) = &self.name_fuzzy {
+            let name =
+                fuzzy::best_match(query, devices.iter().map(|d| d.name.as_str()))?.to_owned();
+            devices.retain(|d| d.name == name);
+        }
+
+        if let Some(query) = &self.search {
+            retain_search(&mut devices, query)?;
+        }
+
+        if let Some(vendor_id) = self.vendor_id {
+            devices.retain(|d| d.vendor_id == vendor_id);
+            if devices.is_empty() {
+                bail!("failed to find device matching vendor id `{}`", vendor_id)
+            }
+        }
+
+        if !self.product_ids.is_empty() {
+            devices.retain(|d| self.product_ids.contains(&d.product_id));
+            if devices.is_empty() {
+                match self.product_ids.as_slice() {
+                    [product_id] => {
+                        bail!("failed to find device matching product id `{}`", product_id)
+                    }
+                    ids => bail!(
+                        "failed to find device matching any of the product ids {}",
+                        quoted(ids)
+                    ),
+                }
+            }
+        }
+
+        if let Some(serial) = &self.serial {
+            devices.retain(|d| d.serial.as_ref() == Some(serial));
+            if devices.is_empty() {
+                bail!("failed to find device matching serial number `{}`", serial)
+            }
+        }
+
+        if let Some(transport) = &self.transport {
+            devices.retain(|d| {
+                d.transport
+                    .as_ref()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(transport))
+            });
+            if devices.is_empty() {
+                bail!("failed to find device matching transport `{}`", transport)
+            }
+        }
+
+        match self.location_id {
+            Some(location_id) => {
+                devices.retain(|d| d.location_id == Some(location_id));
+                if devices.is_empty() {
+                    bail!(
+                        "failed to find device matching location id `{:#x}`",
+                        location_id
+                    )
+                }
+            }
+            None => devices = merge_locations(devices),
+        }
+
+        Ok(devices)
+    }
+}
+
+/// Merge identical keyboards that only differ by location.
+///
+/// A location is only targeted when asked to, so that the mappings still apply
+/// if the keyboard is reconnected elsewhere.
+pub fn merge_locations(mut devices: Vec<Device>) -> Vec<Device> {
+    for d in &mut devices {
+        d.location_id = None;
+    }
+    devices.dedup();
+    devices
+}
+
+/// Returns the transports of the devices if they are the same keyboard
+/// connected with different transports, e.g. USB and Bluetooth.
+pub fn distinct_transports(devices: &[Device]) -> Option<Vec<&str>> {
+    let (first, rest) = devices.split_first()?;
+    let same = |d: &Device| {
+        d.name == first.name && d.vendor_id == first.vendor_id && d.product_id == first.product_id
+    };
+    if rest.is_empty() || !rest.iter().all(same) {
+        return None;
+    }
+    let mut transports = devices
+        .iter()
+        .map(|d| d.transport.as_deref())
+        .collect::<Option<Vec<_>>>()?;
+    transports.sort_unstable();
+    transports.dedup();
+    (transports.len() == devices.len()).then_some(transports)
+}
+
+/// Retain the devices with one of the names, ignoring names prefixed with `!`,
+/// and without any of the names prefixed with `!`.
+fn retain_names(devices: &mut Vec<Device>, names: &[String]) -> Result<()> {
+    let (excluded, included): (Vec<_>, Vec<_>) = names
+        .iter()
+        .map(String::as_str)
+        .partition(|name| name.starts_with('!'));
+    devices.retain(|d| {
+        (included.is_empty() || included.contains(&d.name.as_str()))
+            && !excluded.iter().any(|name| name[1..] == d.name)
+    });
+    if devices.is_empty() {
+        match names {
+            [name] => bail!("failed to find device matching name `{}`", name),
+            names => bail!(
+                "failed to find device matching any of the names {}",
+                quoted(names)
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Retain the devices with a name, manufacturer, or serial number containing
+/// the query, ignoring case.
+fn retain_search(devices: &mut Vec<Device>, query: &str) -> Result<()> {
+    let needle = query.to_lowercase();
+    devices.retain(|d| {
+        [Some(&d.name), d.manufacturer.as_ref(), d.serial.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(&needle))
+    });
+    if devices.is_empty() {
+        bail!("failed to find device matching search `{}`", query)
+    }
+    Ok(())
+}
+
+/// Format the values as a comma separated list of quoted values.
+fn quoted<T: ToString>(values: &[T]) -> String {
+    let values: Vec<_> = values
+        .iter()
+        .map(|v| format!("`{}`", v.to_string()))
+        .collect();
+    values.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, product_id: u64) -> Device {
+        Device::new(0x5ac, product_id, name)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&n| n.to_owned()).collect()
+    }
+
+    #[test]
+    fn selector_retain_names() {
+        let all = vec![
+            device("Apple Internal Keyboard / Trackpad", 0x342),
+            device("USB Keyboard", 0x7692),
+            device("Magic Keyboard", 0x29c),
+        ];
+
+        let mut devices = all.clone();
+        retain_names(&mut devices, &names(&["USB Keyboard"])).unwrap();
+        assert_eq!(devices, [all[1].clone()]);
+
+        let mut devices = all.clone();
+        retain_names(
+            &mut devices,
+            &names(&["!Apple Internal Keyboard / Trackpad"]),
+        )
+        .unwrap();
+        assert_eq!(devices, [all[1].clone(), all[2].clone()]);
+
+        let mut devices = all.clone();
+        retain_names(&mut devices, &names(&["USB Keyboard", "Magic Keyboard"])).unwrap();
+        assert_eq!(devices, [all[1].clone(), all[2].clone()]);
+
+        let mut devices = vec![all[1].clone()];
+        assert_eq!(
+            retain_names(&mut devices, &names(&["!USB Keyboard"]))
+                .unwrap_err()
+                .to_string(),
+            "failed to find device matching name `!USB Keyboard`"
+        );
+
+        let mut devices = all;
+        assert_eq!(
+            retain_names(&mut devices, &names(&["A", "B"]))
+                .unwrap_err()
+                .to_string(),
+            "failed to find device matching any of the names `A`, `B`"
+        );
+    }
+
+    #[test]
+    fn selector_retain_search() {
+        let devices = vec![
+            device("Apple Internal Keyboard / Trackpad", 0x342),
+            device("Keyboard K120", 0xc31c).with_manufacturer(Some("Logitech".to_owned())),
+            device("USB Keyboard", 0x7692).with_serial(Some("a8-91-3D".to_owned())),
+        ];
+
+        let mut d = devices.clone();
+        retain_search(&mut d, "logi").unwrap();
+        assert_eq!(d, [devices[1].clone()]);
+
+        let mut d = devices.clone();
+        retain_search(&mut d, "91-3d").unwrap();
+        assert_eq!(d, [devices[2].clone()]);
+
+        let mut d = devices.clone();
+        retain_search(&mut d, "KEYBOARD").unwrap();
+        assert_eq!(d, devices);
+
+        let mut d = devices;
+        assert_eq!(
+            retain_search(&mut d, "Razer").unwrap_err().to_string(),
+            "failed to find device matching search `Razer`"
+        );
+    }
+
+    #[test]
+    fn selector_and_across_fields() {
+        let devices = vec![
+            device("USB Keyboard", 0x7692),
+            device("USB Keyboard", 0x7693).with_serial(Some("a8-91".to_owned())),
+            Device::new(0xc45, 0x7692, "USB Keyboard"),
+        ];
+
+        let selector = Selector {
+            names: names(&["USB Keyboard"]),
+            vendor_id: Some(0x5ac),
+            ..Default::default()
+        };
+        assert_eq!(selector.filter(devices.clone()).unwrap(), devices[..2]);
+
+        let selector = Selector {
+            vendor_id: Some(0x5ac),
+            product_ids: vec![0x7692],
+            ..Default::default()
+        };
+        assert_eq!(selector.filter(devices.clone()).unwrap(), devices[..1]);
+
+        let selector = Selector {
+            product_ids: vec![0x7693],
+            serial: Some("c4-02".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices).unwrap_err().to_string(),
+            "failed to find device matching serial number `c4-02`"
+        );
+    }
+
+    #[test]
+    fn selector_transport() {
+        let devices = vec![
+            device("Keychron K2", 0x24f).with_transport(Some("USB".to_owned())),
+            device("Keychron K2", 0x24f).with_transport(Some("Bluetooth".to_owned())),
+            device("Magic Keyboard", 0x29c).with_transport(Some("Bluetooth".to_owned())),
+        ];
+
+        let selector = Selector {
+            names: names(&["Keychron K2"]),
+            transport: Some("usb".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices.clone()).unwrap(),
+            [devices[0].clone()]
+        );
+
+        let selector = Selector {
+            transport: Some("SPI".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices.clone()).unwrap_err().to_string(),
+            "failed to find device matching transport `SPI`"
+        );
+
+        assert_eq!(
+            distinct_transports(&devices[..2]),
+            Some(vec!["Bluetooth", "USB"])
+        );
+        assert_eq!(distinct_transports(&devices[1..]), None);
+        assert_eq!(distinct_transports(&devices[..1]), None);
+        let unknown = vec![device("Keychron K2", 0x24f), devices[0].clone()];
+        assert_eq!(distinct_transports(&unknown), None);
+    }
+
+    #[test]
+    fn selector_or_within_field() {
+        let devices = vec![
+            device("Apple Internal Keyboard / Trackpad", 0x342),
+            device("USB Keyboard", 0x7692),
+            device("Magic Keyboard", 0x29c),
+        ];
+
+        let selector = Selector {
+            product_ids: vec![0x342, 0x29c],
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices.clone()).unwrap(),
+            [devices[0].clone(), devices[2].clone()]
+        );
+
+        // OR within the names, AND with the product IDs
+        let selector = Selector {
+            names: names(&["USB Keyboard", "Magic Keyboard"]),
+            product_ids: vec![0x342, 0x29c],
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices.clone()).unwrap(),
+            [devices[2].clone()]
+        );
+
+        let selector = Selector {
+            product_ids: vec![0x1, 0x2],
+            ..Default::default()
+        };
+        assert_eq!(
+            selector.filter(devices).unwrap_err().to_string(),
+            "failed to find device matching any of the product ids `1`, `2`"
+        );
+        assert!(Selector::default().is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::types::Map;
+
+/// A device selector and mappings that can be shared as a single token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_fuzzy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    pub mappings: Vec<Map>,
+}
+
+impl Share {
+    /// Encode as a URL safe base64 token of the JSON representation.
+    pub fn encode(&self) -> Result<String> {
+        let json = serde_json::to_vec(self)?;
+        Ok(URL_SAFE_NO_PAD.encode(json))
+    }
+
+    /// Decode a token created by `Share::encode`.
+    pub fn decode(token: &str) -> Result<Self> {
+        let json = URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .context("failed to decode share token as base64")?;
+        serde_json::from_slice(&json).context("failed to parse share token")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::Key;
+
+    #[test]
+    fn share_round_trip() {
+        let share = Share {
+            name: Some("Apple Internal Keyboard / Trackpad".to_owned()),
+            name_fuzzy: None,
+            vendor_id: Some(0x5ac),
+            product_id: None,
+            serial: Some("a8-91-3d-0e-6f-21".to_owned()),
+            mappings: vec![
+                Map(Key::CapsLock, Key::LeftControl),
+                Map(Key::Char('a'), Key::Raw(0x64)),
+                Map(Key::F(13), Key::Lang(1)),
+            ],
+        };
+        let token = share.encode().unwrap();
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Share::decode(&token).unwrap(), share);
+    }
+
+    #[test]
+    fn share_decode_invalid() {
+        assert_eq!(
+            Share::decode("not base64!").unwrap_err().to_string(),
+            "failed to decode share token as base64"
+        );
+        assert_eq!(
+            Share::decode(&URL_SAFE_NO_PAD.encode(r#"{"mappings":[{"src":"nope","dst":"a"}]}"#))
+                .unwrap_err()
+                .to_string(),
+            "failed to parse share token"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use crate::hex;
+use crate::types::{Key, Map};
+
+/// A keyboard selection and mappings in a tool neutral JSON format, e.g.
+///
+/// ```json
+/// {"device":{"vendor_id":"0xc45"},"maps":[{"from":"capslock","to":"lcontrol"}]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub device: SpecDevice,
+    pub maps: Vec<SpecMap>,
+}
+
+/// The keyboard selection in a spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpecDevice {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_id")]
+    pub vendor_id: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_id")]
+    pub product_id: Option<u64>,
+    #[serde(default)]
+    pub serial: Option<String>,
+}
+
+/// A single mapping in a spec.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpecMap {
+    pub from: Key,
+    pub to: Key,
+}
+
+impl Spec {
+    /// Read and parse the spec file at the given path.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Parse the spec from JSON.
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Returns the mappings in the spec.
+    pub fn mappings(&self) -> Vec<Map> {
+        self.maps.iter().map(|m| Map::new(m.from, m.to)).collect()
+    }
+}
+
+/// Deserialize an optional ID given either as a number or as a hexadecimal
+/// string like the `--json` output.
+fn deserialize_id<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Number(u64),
+        Hex(String),
+    }
+    match Option::<Id>::deserialize(deserializer)? {
+        Some(Id::Number(id)) => Ok(Some(id)),
+        Some(Id::Hex(s)) => hex::parse(&s).map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_parse() {
+        let spec = Spec::parse(
+            r#"{
+  "device": {"vendor_id": "0xc45", "product_id": 30354},
+  "maps": [
+    {"from": "capslock", "to": "lcontrol"},
+    {"from": "a", "to": "0x64"}
+  ]
+}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            spec.device,
+            SpecDevice {
+                name: None,
+                vendor_id: Some(0xc45),
+                product_id: Some(0x7692),
+                serial: None,
+            }
+        );
+        assert_eq!(
+            spec.mappings(),
+            [
+                Map(Key::CapsLock, Key::LeftControl),
+                Map(Key::Char('a'), Key::Raw(0x64)),
+            ]
+        );
+    }
+
+    #[test]
+    fn spec_parse_invalid() {
+        assert!(Spec::parse(r#"{"maps": [{"from": "capslock", "to": "nope"}]}"#).is_err());
+        assert!(Spec::parse(r#"{"maps": [], "extra": 1}"#).is_err());
+        assert_eq!(
+            Spec::parse(r#"{"maps": []}"#).unwrap().device,
+            SpecDevice::default()
+        );
+    }
+}
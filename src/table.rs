@@ -0,0 +1,284 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::hid::{Device, Status};
+
+/// A column in the table of devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    VendorId,
+    ProductId,
+    Name,
+    Serial,
+    Status,
+    Location,
+    Transport,
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let column = match s {
+            "vendor" => Self::VendorId,
+            "product" => Self::ProductId,
+            "name" => Self::Name,
+            "serial" => Self::Serial,
+            "status" => Self::Status,
+            "location" => Self::Location,
+            "transport" => Self::Transport,
+            c => bail!(
+                "unknown column `{}`, expected one of `vendor`, `product`, `name`, `serial`, \
+                 `status`, `location`, or `transport`",
+                c
+            ),
+        };
+        Ok(column)
+    }
+}
+
+impl Column {
+    /// The columns shown when none are specified.
+    pub const DEFAULT: &'static [Column] = &[Self::VendorId, Self::ProductId, Self::Name];
+
+    fn header(self) -> &'static str {
+        match self {
+            Self::VendorId => "Vendor ID",
+            Self::ProductId => "Product ID",
+            Self::Name => "Name",
+            Self::Serial => "Serial Number",
+            Self::Status => "Status",
+            Self::Location => "Location ID",
+            Self::Transport => "Transport",
+        }
+    }
+
+    fn value(self, device: &Device) -> String {
+        match self {
+            Self::VendorId => format!("{:#x}", device.vendor_id),
+            Self::ProductId => format!("{:#x}", device.product_id),
+            Self::Name => device.name.clone(),
+            Self::Serial => device.serial.clone().unwrap_or_default(),
+            Self::Status => match device.status {
+                Some(Status::Connected) => "connected".to_owned(),
+                Some(Status::Remembered) => "remembered".to_owned(),
+                None => String::new(),
+            },
+            Self::Location => device
+                .location_id
+                .map(|id| format!("{:#x}", id))
+                .unwrap_or_default(),
+            Self::Transport => device.transport.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Render the devices as a table with the given columns.
+pub fn tabulate(devices: &[Device], columns: &[Column]) -> String {
+    render(devices, columns, None)
+}
+
+/// Render the devices as a table with the given columns, truncating the names
+/// so that the table fits within the given width where possible.
+pub fn tabulate_fit(devices: &[Device], columns: &[Column], max_width: usize) -> String {
+    render(devices, columns, Some(max_width))
+}
+
+/// The minimum width that names are truncated to.
+const MIN_NAME_WIDTH: usize = 12;
+
+fn render(devices: &[Device], columns: &[Column], max_width: Option<usize>) -> String {
+    let headers: Vec<_> = columns.iter().map(|c| c.header().to_owned()).collect();
+    let mut rows: Vec<Vec<_>> = devices
+        .iter()
+        .map(|d| columns.iter().map(|c| c.value(d)).collect())
+        .collect();
+
+    let mut widths: Vec<_> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .chain([&headers])
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap()
+        })
+        .collect();
+
+    let name = columns.iter().position(|&c| c == Column::Name);
+    if let (Some(max_width), Some(i)) = (max_width, name) {
+        let total: usize = widths.iter().sum::<usize>() + 2 * (widths.len() - 1);
+        if total > max_width {
+            let width = (widths[i] + max_width)
+                .saturating_sub(total)
+                .max(MIN_NAME_WIDTH);
+            if width < widths[i] {
+                widths[i] = width;
+                for row in &mut rows {
+                    row[i] = truncate(&row[i], width);
+                }
+            }
+        }
+    }
+
+    let dashes: Vec<_> = widths.iter().map(|&w| "-".repeat(w)).collect();
+
+    let mut s = String::new();
+    for row in [&headers, &dashes].into_iter().chain(&rows) {
+        for (i, (value, width)) in row.iter().zip(&widths).enumerate() {
+            if i == row.len() - 1 {
+                writeln!(s, "{}", value).unwrap();
+            } else {
+                write!(s, "{:<width$}  ", value, width = width).unwrap();
+            }
+        }
+    }
+    s
+}
+
+/// Truncate the string to the given number of characters, replacing the end
+/// with an ellipsis if it was truncated.
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_owned();
+    }
+    let mut t: String = s.chars().take(width.saturating_sub(1)).collect();
+    t.push('…');
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device {
+                vendor_id: 0x5ac,
+                product_id: 0x342,
+                name: "Apple Internal Keyboard / Trackpad".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
+            },
+            Device {
+                vendor_id: 0xc45,
+                product_id: 0x7692,
+                name: "USB Keyboard".to_owned(),
+                serial: None,
+                manufacturer: None,
+                status: None,
+                location_id: None,
+                registry_id: None,
+                transport: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_tabulate_default() {
+        assert_eq!(
+            tabulate(&devices(), Column::DEFAULT),
+            "\
+Vendor ID  Product ID  Name
+---------  ----------  ----------------------------------
+0x5ac      0x342       Apple Internal Keyboard / Trackpad
+0xc45      0x7692      USB Keyboard
+"
+        );
+    }
+
+    #[test]
+    fn test_tabulate_columns() {
+        let columns: Vec<Column> = "name,vendor"
+            .split(',')
+            .map(|c| c.parse().unwrap())
+            .collect();
+        assert_eq!(
+            tabulate(&devices(), &columns),
+            "\
+Name                                Vendor ID
+----------------------------------  ---------
+Apple Internal Keyboard / Trackpad  0x5ac
+USB Keyboard                        0xc45
+"
+        );
+    }
+
+    #[test]
+    fn test_tabulate_fit() {
+        assert_eq!(
+            tabulate_fit(&devices(), Column::DEFAULT, 40),
+            "\
+Vendor ID  Product ID  Name
+---------  ----------  -----------------
+0x5ac      0x342       Apple Internal K…
+0xc45      0x7692      USB Keyboard
+"
+        );
+        assert_eq!(
+            tabulate_fit(&devices(), Column::DEFAULT, 80),
+            tabulate(&devices(), Column::DEFAULT)
+        );
+    }
+
+    #[test]
+    fn test_tabulate_fit_min_width() {
+        assert_eq!(
+            tabulate_fit(&devices(), &[Column::Name], 5),
+            "\
+Name
+------------
+Apple Inter…
+USB Keyboard
+"
+        );
+    }
+
+    #[test]
+    fn test_truncate_char_boundary() {
+        assert_eq!(truncate("한국어 키보드", 4), "한국어…");
+        assert_eq!(truncate("한국어 키보드", 7), "한국어 키보드");
+        assert_eq!(truncate("Keyboard ⌘", 9), "Keyboard…");
+    }
+
+    #[test]
+    fn test_column_from_str_unknown() {
+        assert_eq!(
+            Column::from_str("bus").unwrap_err().to_string(),
+            "unknown column `bus`, expected one of `vendor`, `product`, `name`, `serial`, \
+             `status`, `location`, or `transport`"
+        );
+    }
+
+    #[test]
+    fn test_tabulate_transport() {
+        let devices = [
+            Device::new(0x5ac, 0x342, "Apple Internal Keyboard / Trackpad")
+                .with_transport(Some("SPI".to_owned()))
+                .with_location_id(Some(0x0)),
+            Device::new(0xc45, 0x7692, "USB Keyboard")
+                .with_transport(Some("USB".to_owned()))
+                .with_location_id(Some(0x14200000)),
+            Device::new(0x5ac, 0x29c, "Magic Keyboard").with_location_id(Some(0x3a4b5c6d)),
+        ];
+        let columns: Vec<Column> = "vendor,product,name,transport,location"
+            .split(',')
+            .map(|c| c.parse().unwrap())
+            .collect();
+        assert_eq!(
+            tabulate(&devices, &columns),
+            "\
+Vendor ID  Product ID  Name                                Transport  Location ID
+---------  ----------  ----------------------------------  ---------  -----------
+0x5ac      0x342       Apple Internal Keyboard / Trackpad  SPI        0x0
+0xc45      0x7692      USB Keyboard                        USB        0x14200000
+0x5ac      0x29c       Magic Keyboard                                 0x3a4b5c6d
+"
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use std::fmt;
 use std::str::FromStr;
 
-use anyhow::{anyhow, bail, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 
 use crate::hex;
 
@@ -15,70 +18,298 @@ pub struct Map(pub Key, pub Key);
 impl FromStr for Mappings {
     type Err = Error;
 
+    /// Parse a comma separated list of mappings, e.g. `capslock:escape,a:b`.
     fn from_str(s: &str) -> Result<Self> {
         if s.is_empty() {
             bail!("empty")
         }
+        let mut mappings = Vec::new();
+        for entry in split_entries(s) {
+            mappings.extend(Self::parse_entry(entry)?.0);
+        }
+        Ok(Self(mappings))
+    }
+}
+
+/// Split a comma separated list of mappings, keeping commas that are part of
+/// a key, e.g. `,:.` or `a:,`. A trailing comma is ignored, and any other
+/// empty entry, e.g. in `a:b,,c:d`, is kept so that it can be rejected.
+fn split_entries(s: &str) -> Vec<&str> {
+    let is_complete = |entry: &str| {
+        entry
+            .split_once(':')
+            .is_some_and(|(src, dst)| !src.is_empty() && !dst.is_empty())
+    };
+    let mut entries = Vec::new();
+    let mut start = 0;
+    for (i, _) in s.match_indices(',') {
+        // a comma at the start of an entry is only a key if a colon follows
+        if i == start && !s[i + 1..].starts_with(':') {
+            entries.push("");
+            start = i + 1;
+        } else if is_complete(&s[start..i]) {
+            entries.push(&s[start..i]);
+            start = i + 1;
+        }
+    }
+    if start < s.len() {
+        entries.push(&s[start..]);
+    }
+    entries
+}
+
+impl Mappings {
+    /// Parse a single mapping, which may expand to multiple mappings if either
+    /// side is a group of keys, e.g. `command:control`.
+    fn parse_entry(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            bail!("empty mapping")
+        }
         let (src, dst) = s
             .split_once(':')
             .ok_or_else(|| anyhow!("colon not found"))?;
 
         enum K {
-            Double { l: Key, r: Key },
+            Group(&'static [Key]),
             Single(Key),
         }
 
         let parse = |s| {
             let m: K = match s {
-                "control" => K::Double {
-                    l: Key::LeftControl,
-                    r: Key::RightControl,
-                },
-                "shift" => K::Double {
-                    l: Key::LeftShift,
-                    r: Key::RightShift,
-                },
-                "option" => K::Double {
-                    l: Key::LeftOption,
-                    r: Key::RightOption,
-                },
-                "command" => K::Double {
-                    l: Key::LeftCommand,
-                    r: Key::RightCommand,
-                },
+                "control" => K::Group(&[Key::LeftControl, Key::RightControl]),
+                "shift" => K::Group(&[Key::LeftShift, Key::RightShift]),
+                "option" => K::Group(&[Key::LeftOption, Key::RightOption]),
+                "command" => K::Group(&[Key::LeftCommand, Key::RightCommand]),
+                "modifiers" => K::Group(&[
+                    Key::LeftControl,
+                    Key::LeftShift,
+                    Key::LeftOption,
+                    Key::LeftCommand,
+                    Key::RightControl,
+                    Key::RightShift,
+                    Key::RightOption,
+                    Key::RightCommand,
+                ]),
+                "lmodifiers" | "left+modifiers" => K::Group(&[
+                    Key::LeftControl,
+                    Key::LeftShift,
+                    Key::LeftOption,
+                    Key::LeftCommand,
+                ]),
+                "rmodifiers" | "right+modifiers" => K::Group(&[
+                    Key::RightControl,
+                    Key::RightShift,
+                    Key::RightOption,
+                    Key::RightCommand,
+                ]),
                 src => K::Single(src.parse()?),
             };
             Ok::<_, Error>(m)
         };
 
-        fn map(src: K, dst: K) -> Vec<Map> {
-            match (src, dst) {
-                (K::Double { l: l0, r: r0 }, K::Double { l: l1, r: r1 }) => {
-                    vec![Map(l0, l1), Map(r0, r1)]
-                }
-                (K::Double { l, r }, K::Single(dst)) => {
-                    vec![Map(l, dst), Map(r, dst)]
-                }
-                (K::Single(src), K::Double { l, r }) => {
-                    vec![Map(src, l), Map(src, r)]
+        fn pairs(src: K, dst: K) -> Result<Vec<(Key, Key)>> {
+            let pairs = match (src, dst) {
+                (K::Group(src), K::Group(dst)) => {
+                    if src.len() != dst.len() {
+                        bail!("cannot map {} keys to {} keys", src.len(), dst.len())
+                    }
+                    src.iter().copied().zip(dst.iter().copied()).collect()
                 }
+                (K::Group(src), K::Single(dst)) => src.iter().map(|&s| (s, dst)).collect(),
+                (K::Single(src), K::Group(dst)) => dst.iter().map(|&d| (src, d)).collect(),
                 (K::Single(src), K::Single(dst)) => {
-                    vec![Map(src, dst)]
+                    vec![(src, dst)]
                 }
+            };
+            Ok(pairs)
+        }
+
+        let src = parse(src)?;
+        let dst = parse(dst).map_err(|err| {
+            if is_literal(dst) {
+                anyhow!(
+                    "destination '{}' is not a single key; only single-key remaps are supported",
+                    dst
+                )
+            } else {
+                err
             }
+        })?;
+        let pairs = pairs(src, dst)?;
+        // compare the named keys so that a raw usage matches its named key
+        if let Some((src, _)) = pairs.iter().find(|(src, dst)| src.named() == dst.named()) {
+            bail!("source and destination are the same key `{}`", src.code())
         }
+        Ok(Self::from_pairs(&pairs))
+    }
 
-        Ok(Self(map(parse(src)?, parse(dst)?)))
+    /// Returns new mappings from each source key to destination key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kb_remap::types::{Key, Mappings};
+    ///
+    /// let pairs = [(Key::CapsLock, Key::Escape), (Key::Char('a'), Key::Char('b'))];
+    /// let mappings = Mappings::from_pairs(&pairs);
+    /// assert_eq!(mappings.to_pairs(), pairs);
+    /// assert_eq!(
+    ///     serde_json::to_string(&mappings.0).unwrap(),
+    ///     r#"[{"src":"capslock","dst":"escape"},{"src":"a","dst":"b"}]"#
+    /// );
+    /// ```
+    pub fn from_pairs(pairs: &[(Key, Key)]) -> Self {
+        Self(pairs.iter().map(|&(src, dst)| Map(src, dst)).collect())
+    }
+
+    /// Returns the source and destination key of each mapping.
+    pub fn to_pairs(&self) -> Vec<(Key, Key)> {
+        self.0.iter().map(|&Map(src, dst)| (src, dst)).collect()
+    }
+}
+
+/// Whether the string looks like literal text rather than an attempt at a
+/// key name, raw usage, or numbered key.
+fn is_literal(s: &str) -> bool {
+    s.chars().count() > 1 && KeyForm::of(&s.to_lowercase()).is_none()
+}
+
+/// A form of key that is recognized by its prefix, with the rest of the
+/// lowercase key.
+#[derive(Debug, PartialEq, Eq)]
+enum KeyForm<'a> {
+    /// A QMK keycode, e.g. `kc_esc`.
+    Qmk(&'a str),
+    /// An Apple technote name, e.g. `keyboard caps lock` or `keypad 1`.
+    Technote(&'a str),
+    /// A keypad key, e.g. `kp1`.
+    Keypad(&'a str),
+    /// A function key, e.g. `f5`.
+    Function(&'a str),
+    /// A language key, e.g. `lang1`.
+    Lang(&'a str),
+    /// The debug output of older versions, e.g. `raw(30064771129)`.
+    Raw(&'a str),
+    /// A raw usage, e.g. `0x700000039`.
+    Hex,
+}
+
+impl<'a> KeyForm<'a> {
+    fn of(m: &'a str) -> Option<Self> {
+        let numbered = |n: &&str| n.starts_with(|c: char| c.is_ascii_digit());
+        if let Some(qmk) = m.strip_prefix("kc_") {
+            Some(Self::Qmk(qmk))
+        } else if let Some(name) = m.strip_prefix("keyboard ") {
+            Some(Self::Technote(name))
+        } else if m.starts_with("keypad ") {
+            Some(Self::Technote(m))
+        } else if let Some(name) = m.strip_prefix("kp") {
+            Some(Self::Keypad(name))
+        } else if let Some(n) = m.strip_prefix('f').filter(numbered) {
+            Some(Self::Function(n))
+        } else if let Some(n) = m.strip_prefix("lang").filter(numbered) {
+            Some(Self::Lang(n))
+        } else if let Some(raw) = m.strip_prefix("raw(") {
+            Some(Self::Raw(raw))
+        } else if m.starts_with("0x") {
+            Some(Self::Hex)
+        } else {
+            None
+        }
     }
 }
 
 impl Map {
+    /// Returns a new mapping from `src` to `dst`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kb_remap::types::{Key, Map};
+    ///
+    /// let map = Map::new(Key::CapsLock, Key::Escape);
+    /// assert_eq!(map.src(), Key::CapsLock);
+    /// assert_eq!(map.dst(), Key::Escape);
+    /// assert_eq!(map.swapped().src(), Key::Escape);
+    /// ```
+    pub fn new(src: Key, dst: Key) -> Self {
+        Self(src, dst)
+    }
+
+    /// Returns the source key.
+    pub fn src(&self) -> Key {
+        self.0
+    }
+
+    /// Returns the destination key.
+    pub fn dst(&self) -> Key {
+        self.1
+    }
+
     /// Returns a new modification with the source and destination swapped.
     pub fn swapped(self) -> Self {
-        Self(self.1, self.0)
+        Self::new(self.dst(), self.src())
+    }
+}
+
+impl Serialize for Map {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("Map", 2)?;
+        s.serialize_field("src", &self.0)?;
+        s.serialize_field("dst", &self.1)?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Map {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(serde::Deserialize)]
+        struct Fields {
+            src: Key,
+            dst: Key,
+        }
+        let Fields { src, dst } = Fields::deserialize(deserializer)?;
+        Ok(Self(src, dst))
+    }
+}
+
+impl<'de> Deserialize<'de> for Mappings {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| de::Error::custom(format!("invalid mapping `{}`: {}", s, e)))
     }
 }
 
+/// The names of the non-digit keypad keys, used after the `kp` prefix.
+const KEYPAD_NAMES: &[(char, &str)] = &[
+    ('/', "slash"),
+    ('*', "asterisk"),
+    ('-', "minus"),
+    ('+', "plus"),
+    ('.', "dot"),
+];
+
+/// Returns the key to sort mappings by so that the same set of mappings always
+/// serializes the same way regardless of the order they were given in.
+pub fn sort_key(Map(src, dst): &Map) -> (u64, Option<u64>, u64, Option<u64>) {
+    (
+        src.usage_page_id(),
+        src.usage_id(),
+        dst.usage_page_id(),
+        dst.usage_id(),
+    )
+}
+
 /// A user representation of a key on a keyboard.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[non_exhaustive]
@@ -87,8 +318,12 @@ pub enum Key {
     Return,
     /// ESC
     Escape,
-    /// ⌫
+    /// ⌫, the key labelled delete on Mac keyboards and backspace elsewhere.
+    ///
+    /// This is not the forward delete key, see `ForwardDelete`.
     Delete,
+    /// ⌦, forward delete.
+    ForwardDelete,
     /// ⇪
     CapsLock,
     /// Left ⌃
@@ -109,18 +344,62 @@ pub enum Key {
     RightCommand,
     /// fn
     Fn,
+    /// →
+    Right,
+    /// ←
+    Left,
+    /// ↓
+    Down,
+    /// ↑
+    Up,
+    /// Volume up, on the consumer page.
+    VolumeUp,
+    /// Volume down, on the consumer page.
+    VolumeDown,
+    /// Mute, on the consumer page.
+    Mute,
+    /// Play / pause, on the consumer page.
+    PlayPause,
+    /// Next track, on the consumer page.
+    NextTrack,
+    /// Previous track, on the consumer page.
+    PrevTrack,
+    /// Display brightness up, on the consumer page.
+    BrightnessUp,
+    /// Display brightness down, on the consumer page.
+    BrightnessDown,
 
     /// A character on the keyboard.
     Char(char),
 
+    /// A key on the numeric keypad e.g. `Keypad('1')` or `Keypad('+')`.
+    ///
+    /// The keypad has the digits and `/`, `*`, `-`, `+`, and `.` keys.
+    Keypad(char),
+
+    /// Keypad ENTER.
+    KeypadEnter,
+
+    /// Keypad Num Lock and Clear.
+    NumLock,
+
     /// A function key e.g. F1, F2, F3, etc.
     F(u8),
 
+    /// A language key e.g. LANG1, LANG2, LANG3, etc.
+    ///
+    /// LANG1 and LANG2 are the 한/영 (Hangul) and 한자 (Hanja) keys on Korean
+    /// keyboards and the かな (Kana) and 英数 (Eisu) keys on Apple JIS keyboards.
+    Lang(u8),
+
     /// Any key by its usage ID.
     ///
     /// This can be used to represent any key that is not enumerated in this
     /// type. See USB HID Usage Tables Specification, Section 10 Keyboard/Keypad
     /// Page for exact values for each key.
+    ///
+    /// If the value is larger than 32 bits then it is assumed to already
+    /// include the usage page, e.g. `0x700000039`, and is used as is.
     Raw(u64),
 }
 
@@ -131,39 +410,400 @@ impl FromStr for Key {
         let key = match s.to_lowercase().as_str() {
             "return" => Key::Return,
             "escape" => Key::Escape,
-            "delete" => Key::Delete,
+            "delete" | "backspace" => Key::Delete,
+            "forwarddelete" => Key::ForwardDelete,
             "capslock" => Key::CapsLock,
-            "lcontrol" => Key::LeftControl,
-            "rcontrol" => Key::RightControl,
-            "lshift" => Key::LeftShift,
-            "rshift" => Key::RightShift,
-            "loption" => Key::LeftOption,
-            "roption" => Key::RightOption,
-            "lcommand" => Key::LeftCommand,
-            "rcommand" => Key::RightCommand,
+            "lcontrol" | "left+control" => Key::LeftControl,
+            "rcontrol" | "right+control" => Key::RightControl,
+            "lshift" | "left+shift" => Key::LeftShift,
+            "rshift" | "right+shift" => Key::RightShift,
+            "loption" | "left+option" => Key::LeftOption,
+            "roption" | "right+option" => Key::RightOption,
+            "lcommand" | "left+command" => Key::LeftCommand,
+            "rcommand" | "right+command" => Key::RightCommand,
             "fn" => Key::Fn,
-            m => {
-                if m.chars().count() == 1 {
-                    return Ok(Key::Char(s.chars().next().unwrap()));
-                } else if let Some(f) = m.strip_prefix('f') {
+            "right" => Key::Right,
+            "left" => Key::Left,
+            "down" => Key::Down,
+            "up" => Key::Up,
+            "volumeup" => Key::VolumeUp,
+            "volumedown" => Key::VolumeDown,
+            "mute" => Key::Mute,
+            "playpause" => Key::PlayPause,
+            "nexttrack" => Key::NextTrack,
+            "prevtrack" => Key::PrevTrack,
+            "brightnessup" => Key::BrightnessUp,
+            "brightnessdown" => Key::BrightnessDown,
+            "hangul" | "kana" => Key::Lang(1),
+            "hanja" | "eisu" => Key::Lang(2),
+            "kpenter" => Key::KeypadEnter,
+            "numlock" => Key::NumLock,
+            m => match KeyForm::of(m) {
+                Some(KeyForm::Qmk(qmk)) => {
+                    Key::from_qmk(qmk).ok_or_else(|| anyhow!("unknown QMK key `{}`", s))?
+                }
+                Some(KeyForm::Technote(name)) => {
+                    Key::from_tn2450(name).ok_or_else(|| anyhow!("unknown technote key `{}`", s))?
+                }
+                Some(KeyForm::Keypad(name)) => Key::from_keypad_name(name)
+                    .ok_or_else(|| anyhow!("unknown keypad key `{}`", s))?,
+                Some(KeyForm::Function(f)) => {
                     let num: u8 = f.parse()?;
                     if !(1..=24).contains(&num) {
                         bail!("invalid function key number: {}", num);
                     }
-                    return Ok(Key::F(num));
+                    Key::F(num)
                 }
-                hex::parse(m).map(Key::Raw)?
-            }
+                Some(KeyForm::Lang(l)) => {
+                    let num: u8 = l.parse()?;
+                    if !(1..=9).contains(&num) {
+                        bail!("invalid language key number: {}", num);
+                    }
+                    Key::Lang(num)
+                }
+                Some(KeyForm::Raw(raw)) => {
+                    let raw = raw
+                        .strip_suffix(')')
+                        .ok_or_else(|| anyhow!("expected `)` at the end of `{}`", s))?;
+                    let raw = match raw.strip_prefix("0x") {
+                        Some(_) => hex::parse(raw)?,
+                        None => raw
+                            .parse()
+                            .with_context(|| format!("failed to parse `{}` as an integer", raw))?,
+                    };
+                    Key::Raw(raw)
+                }
+                Some(KeyForm::Hex) => hex::parse(m).map(Key::Raw)?,
+                None if m.chars().count() == 1 => Key::Char(s.chars().next().unwrap()),
+                None => hex::parse(m).map(Key::Raw)?,
+            },
         };
         Ok(key)
     }
 }
 
+impl fmt::Display for Key {
+    /// Formats the key for people, e.g. `CapsLock`, `a`, `F5`, or `0x64`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Char(' ') => f.write_str("Space"),
+            Self::Char('\t') => f.write_str("Tab"),
+            Self::Char(c) => write!(f, "{}", c),
+            Self::Keypad(c) => write!(f, "Keypad{}", c),
+            Self::F(num) => write!(f, "F{}", num),
+            Self::Lang(num) => write!(f, "Lang{}", num),
+            Self::Raw(raw) => write!(f, "{:#x}", raw),
+            key => write!(f, "{:?}", key),
+        }
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.code())
+    }
+}
+
+impl TryFrom<u64> for Key {
+    type Error = Error;
+
+    /// Returns the key for a combined usage page and usage ID, e.g. as output
+    /// by `hidutil property --get`.
+    ///
+    /// Usages on pages other than the keyboard and fn pages are returned as
+    /// `Key::Raw(..)` with the usage page included.
+    fn try_from(usage: u64) -> Result<Self> {
+        let (page, id) = (usage & !0xffff_ffff, usage & 0xffff_ffff);
+        if page == 0 {
+            bail!("`{:#x}` does not include a usage page", usage);
+        }
+        Ok(Self::from_usage(page, id))
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(de::Error::custom)
+    }
+}
+
 impl Key {
+    /// Returns the key for a usage page ID, in the form returned by
+    /// `usage_page_id`, and a usage ID. This is the inverse of `usage_page_id`
+    /// and `usage_id`.
+    ///
+    /// Usages that are not a named key are returned as `Key::Raw(..)`, with the
+    /// usage page included if it is not the keyboard page.
+    pub fn from_usage(page_id: u64, usage_id: u64) -> Self {
+        const CHARS: &str = "abcdefghijklmnopqrstuvwxyz1234567890\t -=[]\\;'`,./";
+        const KEYPAD: &str = "1234567890/*-+.";
+
+        match page_id {
+            0xff_0000_0000 if usage_id == 0x03 => Key::Fn,
+            0xc_0000_0000 => [
+                Key::VolumeUp,
+                Key::VolumeDown,
+                Key::Mute,
+                Key::PlayPause,
+                Key::NextTrack,
+                Key::PrevTrack,
+                Key::BrightnessUp,
+                Key::BrightnessDown,
+            ]
+            .into_iter()
+            .find(|k| k.usage_id() == Some(usage_id))
+            .unwrap_or(Key::Raw(page_id + usage_id)),
+            0x7_0000_0000 => [
+                Key::Return,
+                Key::Escape,
+                Key::Delete,
+                Key::ForwardDelete,
+                Key::CapsLock,
+                Key::LeftControl,
+                Key::LeftShift,
+                Key::LeftOption,
+                Key::LeftCommand,
+                Key::RightControl,
+                Key::RightShift,
+                Key::RightOption,
+                Key::RightCommand,
+                Key::Right,
+                Key::Left,
+                Key::Down,
+                Key::Up,
+                Key::KeypadEnter,
+                Key::NumLock,
+            ]
+            .into_iter()
+            .chain(CHARS.chars().map(Key::Char))
+            .chain(KEYPAD.chars().map(Key::Keypad))
+            .chain((1..=24).map(Key::F))
+            .chain((1..=9).map(Key::Lang))
+            .find(|k| k.usage_id() == Some(usage_id))
+            .unwrap_or(Key::Raw(usage_id)),
+            _ => Key::Raw(page_id + usage_id),
+        }
+    }
+
+    /// Returns the named key for a raw usage, e.g. `Key::Raw(0x700000039)` is
+    /// `Key::CapsLock`. Other keys are returned unchanged.
+    pub fn named(self) -> Self {
+        match self {
+            Self::Raw(raw) => {
+                let usage = self.usage_page_id() + raw;
+                Self::from_usage(usage & !0xffff_ffff, usage & 0xffff_ffff)
+            }
+            key => key,
+        }
+    }
+
+    /// Returns the code for this key, this is parseable by `Key::from_str`.
+    pub fn code(&self) -> String {
+        let code = match self {
+            Self::Return => "return",
+            Self::Escape => "escape",
+            Self::Delete => "delete",
+            Self::ForwardDelete => "forwarddelete",
+            Self::CapsLock => "capslock",
+            Self::LeftControl => "lcontrol",
+            Self::LeftShift => "lshift",
+            Self::LeftOption => "loption",
+            Self::LeftCommand => "lcommand",
+            Self::RightControl => "rcontrol",
+            Self::RightShift => "rshift",
+            Self::RightOption => "roption",
+            Self::RightCommand => "rcommand",
+            Self::Fn => "fn",
+            Self::Right => "right",
+            Self::Left => "left",
+            Self::Down => "down",
+            Self::Up => "up",
+            Self::VolumeUp => "volumeup",
+            Self::VolumeDown => "volumedown",
+            Self::Mute => "mute",
+            Self::PlayPause => "playpause",
+            Self::NextTrack => "nexttrack",
+            Self::PrevTrack => "prevtrack",
+            Self::BrightnessUp => "brightnessup",
+            Self::BrightnessDown => "brightnessdown",
+            Self::KeypadEnter => "kpenter",
+            Self::NumLock => "numlock",
+            Self::Char(c) => return c.to_string(),
+            Self::Keypad(c) => match KEYPAD_NAMES.iter().find(|(k, _)| k == c) {
+                Some((_, name)) => return format!("kp{}", name),
+                None => return format!("kp{}", c),
+            },
+            Self::F(num) => return format!("f{}", num),
+            Self::Lang(num) => return format!("lang{}", num),
+            Self::Raw(raw) => return format!("{:#x}", raw),
+        };
+        code.to_owned()
+    }
+
+    /// Returns the keypad key for the given name without the `kp` prefix, e.g.
+    /// `1` or `plus`.
+    fn from_keypad_name(name: &str) -> Option<Self> {
+        if let Some((c, _)) = KEYPAD_NAMES.iter().find(|(_, n)| *n == name) {
+            return Some(Self::Keypad(*c));
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_digit() => Some(Self::Keypad(c)),
+            _ => None,
+        }
+    }
+
+    /// Returns the key for the given QMK keycode name without the `KC_` prefix.
+    ///
+    /// See https://docs.qmk.fm/keycodes_basic
+    fn from_qmk(name: &str) -> Option<Self> {
+        let key = match name {
+            "ent" | "enter" => Self::Return,
+            "esc" | "escape" => Self::Escape,
+            "bspc" | "backspace" => Self::Delete,
+            "caps" | "caps_lock" => Self::CapsLock,
+            "lctl" | "left_ctrl" => Self::LeftControl,
+            "lsft" | "left_shift" => Self::LeftShift,
+            "lalt" | "lopt" | "left_alt" => Self::LeftOption,
+            "lgui" | "lcmd" | "left_gui" => Self::LeftCommand,
+            "rctl" | "right_ctrl" => Self::RightControl,
+            "rsft" | "right_shift" => Self::RightShift,
+            "ralt" | "ropt" | "right_alt" => Self::RightOption,
+            "rgui" | "rcmd" | "right_gui" => Self::RightCommand,
+            "tab" => Self::Char('\t'),
+            "spc" | "space" => Self::Char(' '),
+            "mins" | "minus" => Self::Char('-'),
+            "eql" | "equal" => Self::Char('='),
+            "lbrc" | "left_bracket" => Self::Char('['),
+            "rbrc" | "right_bracket" => Self::Char(']'),
+            "bsls" | "backslash" => Self::Char('\\'),
+            "scln" | "semicolon" => Self::Char(';'),
+            "quot" | "quote" => Self::Char('\''),
+            "grv" | "grave" => Self::Char('`'),
+            "comm" | "comma" => Self::Char(','),
+            "dot" => Self::Char('.'),
+            "slsh" | "slash" => Self::Char('/'),
+            "del" | "delete" => Self::ForwardDelete,
+            "rght" | "right" => Self::Right,
+            "left" => Self::Left,
+            "down" => Self::Down,
+            "up" => Self::Up,
+            "volu" | "audio_vol_up" => Self::VolumeUp,
+            "vold" | "audio_vol_down" => Self::VolumeDown,
+            "mute" | "audio_mute" => Self::Mute,
+            "mply" | "media_play_pause" => Self::PlayPause,
+            "mnxt" | "media_next_track" => Self::NextTrack,
+            "mprv" | "media_prev_track" => Self::PrevTrack,
+            "briu" | "brightness_up" => Self::BrightnessUp,
+            "brid" | "brightness_down" => Self::BrightnessDown,
+            "nubs" | "nonus_backslash" => Self::Raw(0x64),
+            "num" | "nlck" | "num_lock" => Self::NumLock,
+            "psls" | "kp_slash" => Self::Keypad('/'),
+            "past" | "kp_asterisk" => Self::Keypad('*'),
+            "pmns" | "kp_minus" => Self::Keypad('-'),
+            "ppls" | "kp_plus" => Self::Keypad('+'),
+            "pent" | "kp_enter" => Self::KeypadEnter,
+            "pdot" | "kp_dot" => Self::Keypad('.'),
+            name => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii_alphanumeric() => Self::Char(c),
+                    (Some('p'), Some(c)) if c.is_ascii_digit() && chars.next().is_none() => {
+                        Self::Keypad(c)
+                    }
+                    _ => match name.strip_prefix('f').map(str::parse) {
+                        Some(Ok(num @ 1..=24)) => Self::F(num),
+                        _ => return None,
+                    },
+                }
+            }
+        };
+        Some(key)
+    }
+
+    /// Returns the key for the given usage name from Apple's technote without
+    /// the `Keyboard` prefix, e.g. `Left GUI` or `a and A`.
+    ///
+    /// Whitespace is ignored so `LeftControl` and `Left Control` are the same.
+    ///
+    /// See https://developer.apple.com/library/archive/technotes/tn2450/_index.html
+    fn from_tn2450(name: &str) -> Option<Self> {
+        let name: String = name.split_whitespace().collect();
+        let key = match name.as_str() {
+            "return(enter)" | "return" => Self::Return,
+            "escape" => Self::Escape,
+            "delete(backspace)" => Self::Delete,
+            "capslock" => Self::CapsLock,
+            "leftcontrol" => Self::LeftControl,
+            "leftshift" => Self::LeftShift,
+            "leftalt" => Self::LeftOption,
+            "leftgui" => Self::LeftCommand,
+            "rightcontrol" => Self::RightControl,
+            "rightshift" => Self::RightShift,
+            "rightalt" => Self::RightOption,
+            "rightgui" => Self::RightCommand,
+            "tab" => Self::Char('\t'),
+            "spacebar" => Self::Char(' '),
+            "graveaccentandtilde" => Self::Char('`'),
+            "deleteforward" => Self::ForwardDelete,
+            "rightarrow" => Self::Right,
+            "leftarrow" => Self::Left,
+            "downarrow" => Self::Down,
+            "uparrow" => Self::Up,
+            "non-us\\and|" => Self::Raw(0x64),
+            "keypadnumlockandclear" => Self::NumLock,
+            "keypadenter" => Self::KeypadEnter,
+            name if name.starts_with("keypad") => {
+                // e.g. "Keypad /", "Keypad 1 and End", "Keypad . and Delete"
+                let mut chars = name["keypad".len()..].chars();
+                match (chars.next(), chars.as_str()) {
+                    (Some(c), rest)
+                        if (rest.is_empty() || rest.starts_with("and"))
+                            && Self::Keypad(c).usage_id().is_some() =>
+                    {
+                        Self::Keypad(c)
+                    }
+                    _ => return None,
+                }
+            }
+            name => {
+                // e.g. "a and A", "1 and !", "- and (underscore)"
+                let mut chars = name.chars();
+                match (chars.next(), chars.as_str().starts_with("and")) {
+                    (Some(c), true) if Self::Char(c).usage_id().is_some() => Self::Char(c),
+                    _ => match name.strip_prefix('f').map(str::parse) {
+                        Some(Ok(num @ 1..=24)) => Self::F(num),
+                        _ => match name.strip_prefix("lang").map(str::parse) {
+                            Some(Ok(num @ 1..=9)) => Self::Lang(num),
+                            _ => return None,
+                        },
+                    },
+                }
+            }
+        };
+        Some(key)
+    }
+
     /// Returns the usage page ID for this key.
     pub fn usage_page_id(&self) -> u64 {
         match self {
             Key::Fn => 0xff_0000_0000,
+            Key::VolumeUp
+            | Key::VolumeDown
+            | Key::Mute
+            | Key::PlayPause
+            | Key::NextTrack
+            | Key::PrevTrack
+            | Key::BrightnessUp
+            | Key::BrightnessDown => 0xc_0000_0000,
+            Key::Raw(raw) if raw >> 32 != 0 => 0,
             _ => 0x7_0000_0000,
         }
     }
@@ -175,6 +815,7 @@ impl Key {
             Self::Return => 0x28,
             Self::Escape => 0x29,
             Self::Delete => 0x2a,
+            Self::ForwardDelete => 0x4c,
             Self::CapsLock => 0x39,
             Self::LeftControl => 0xe0,
             Self::LeftShift => 0xe1,
@@ -185,6 +826,30 @@ impl Key {
             Self::RightOption => 0xe6,
             Self::RightCommand => 0xe7,
             Self::Fn => 0x03,
+            Self::Right => 0x4f,
+            Self::Left => 0x50,
+            Self::Down => 0x51,
+            Self::Up => 0x52,
+            Self::VolumeUp => 0xe9,
+            Self::VolumeDown => 0xea,
+            Self::Mute => 0xe2,
+            Self::PlayPause => 0xcd,
+            Self::NextTrack => 0xb5,
+            Self::PrevTrack => 0xb6,
+            Self::BrightnessUp => 0x6f,
+            Self::BrightnessDown => 0x70,
+            Self::NumLock => 0x53,
+            Self::KeypadEnter => 0x58,
+            Self::Keypad(c) => match c {
+                '/' => 0x54,
+                '*' => 0x55,
+                '-' => 0x56,
+                '+' => 0x57,
+                '1'..='9' => 0x59 + u64::from(*c) - u64::from('1'),
+                '0' => 0x62,
+                '.' => 0x63,
+                _ => return None,
+            },
             Self::Char(c) => match c {
                 'a' | 'A' => 0x04,
                 'b' | 'B' => 0x05,
@@ -265,7 +930,13 @@ impl Key {
                 22 => 0x71,
                 23 => 0x72,
                 24 => 0x73,
-                _ => unreachable!(),
+                // only F1 to F24 are parsed but the variant can be constructed
+                // with any number
+                _ => return None,
+            },
+            &Self::Lang(num) => match num {
+                1..=9 => 0x8f + u64::from(num),
+                _ => return None,
             },
             Self::Raw(raw) => *raw,
         };
@@ -308,11 +979,232 @@ mod tests {
         }
     }
 
+    #[test]
+    fn mod_from_str_same_key() {
+        for (s, key) in [
+            ("a:a", "a"),
+            ("capslock:0x39", "capslock"),
+            // only one side of the group collapses
+            ("control:lcontrol", "lcontrol"),
+            ("rshift:shift", "rshift"),
+            ("lmodifiers:lcommand", "lcommand"),
+        ] {
+            assert_eq!(
+                Mappings::from_str(s).unwrap_err().to_string(),
+                format!("source and destination are the same key `{}`", key),
+                "{}",
+                s
+            );
+        }
+        assert_eq!(Mappings::from_str("control:command").unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn mod_from_str_modifiers() {
+        assert_eq!(
+            Mappings::from_str("modifiers:0x0").unwrap().0,
+            [
+                Map(Key::LeftControl, Key::Raw(0x0)),
+                Map(Key::LeftShift, Key::Raw(0x0)),
+                Map(Key::LeftOption, Key::Raw(0x0)),
+                Map(Key::LeftCommand, Key::Raw(0x0)),
+                Map(Key::RightControl, Key::Raw(0x0)),
+                Map(Key::RightShift, Key::Raw(0x0)),
+                Map(Key::RightOption, Key::Raw(0x0)),
+                Map(Key::RightCommand, Key::Raw(0x0)),
+            ]
+        );
+        assert_eq!(
+            Mappings::from_str("modifiers:control")
+                .unwrap_err()
+                .to_string(),
+            "cannot map 8 keys to 2 keys"
+        );
+    }
+
+    #[test]
+    fn mod_from_str_side_modifiers() {
+        assert_eq!(
+            Mappings::from_str("right+modifiers:0x0").unwrap().0,
+            [
+                Map(Key::RightControl, Key::Raw(0x0)),
+                Map(Key::RightShift, Key::Raw(0x0)),
+                Map(Key::RightOption, Key::Raw(0x0)),
+                Map(Key::RightCommand, Key::Raw(0x0)),
+            ]
+        );
+        assert_eq!(
+            Mappings::from_str("rmodifiers:lmodifiers").unwrap().0,
+            [
+                Map(Key::RightControl, Key::LeftControl),
+                Map(Key::RightShift, Key::LeftShift),
+                Map(Key::RightOption, Key::LeftOption),
+                Map(Key::RightCommand, Key::LeftCommand),
+            ]
+        );
+        assert_eq!(
+            Mappings::from_str("rmodifiers:modifiers")
+                .unwrap_err()
+                .to_string(),
+            "cannot map 4 keys to 8 keys"
+        );
+    }
+
+    #[test]
+    fn mod_from_str_comma_separated() {
+        assert_eq!(
+            Mappings::from_str("capslock:escape,lcommand:loption")
+                .unwrap()
+                .0,
+            [
+                Map(Key::CapsLock, Key::Escape),
+                Map(Key::LeftCommand, Key::LeftOption)
+            ]
+        );
+        assert_eq!(
+            Mappings::from_str("capslock:escape,").unwrap().0,
+            [Map(Key::CapsLock, Key::Escape)]
+        );
+        assert_eq!(
+            Mappings::from_str(",:.,a:,,.:b").unwrap().0,
+            [
+                Map(Key::Char(','), Key::Char('.')),
+                Map(Key::Char('a'), Key::Char(',')),
+                Map(Key::Char('.'), Key::Char('b')),
+            ]
+        );
+        assert_eq!(
+            Mappings::from_str("capslock:escape,lcommand")
+                .unwrap_err()
+                .to_string(),
+            "colon not found"
+        );
+        for s in ["a:b,,c:d", ",a:b", "a:b,,"] {
+            assert_eq!(
+                Mappings::from_str(s).unwrap_err().to_string(),
+                "empty mapping",
+                "{}",
+                s
+            );
+        }
+        assert_eq!(split_entries("a:b,,c:d"), ["a:b", "", "c:d"]);
+        assert_eq!(split_entries("a:b,,:."), ["a:b", ",:."]);
+    }
+
+    #[test]
+    fn mod_from_str_literal_destination() {
+        assert_eq!(
+            Mappings::from_str("capslock:xyz").unwrap_err().to_string(),
+            "destination 'xyz' is not a single key; only single-key remaps are supported"
+        );
+        assert_eq!(
+            Mappings::from_str("capslock:0xzz").unwrap_err().to_string(),
+            "failed to parse `0xzz` as hexadecimal"
+        );
+        assert_eq!(
+            Mappings::from_str("capslock:f25").unwrap_err().to_string(),
+            "invalid function key number: 25"
+        );
+    }
+
+    #[test]
+    fn mod_from_str_prefixed_destination() {
+        // each prefixed form gets its own error instead of the literal one
+        let cases = [
+            ("kc_nope", "unknown QMK key `kc_nope`"),
+            ("keyboard nope", "unknown technote key `keyboard nope`"),
+            ("Keypad Nope", "unknown technote key `Keypad Nope`"),
+            ("kpnope", "unknown keypad key `kpnope`"),
+            ("f99", "invalid function key number: 99"),
+            ("lang10", "invalid language key number: 10"),
+            ("raw(zz)", "failed to parse `zz` as an integer"),
+            ("raw(0x39", "expected `)` at the end of `raw(0x39`"),
+            ("0xzz", "failed to parse `0xzz` as hexadecimal"),
+        ];
+        for (dst, err) in cases {
+            assert!(!is_literal(dst), "{}", dst);
+            assert_eq!(
+                Mappings::from_str(&format!("capslock:{}", dst))
+                    .unwrap_err()
+                    .to_string(),
+                err
+            );
+        }
+        for dst in ["xyz", "foo", "language", "keyboardx"] {
+            assert!(is_literal(dst), "{}", dst);
+        }
+        assert!(!is_literal("f"));
+    }
+
+    #[test]
+    fn key_form() {
+        assert_eq!(KeyForm::of("kc_esc"), Some(KeyForm::Qmk("esc")));
+        assert_eq!(KeyForm::of("keypad 1"), Some(KeyForm::Technote("keypad 1")));
+        assert_eq!(KeyForm::of("kp1"), Some(KeyForm::Keypad("1")));
+        assert_eq!(KeyForm::of("f5"), Some(KeyForm::Function("5")));
+        assert_eq!(KeyForm::of("lang1"), Some(KeyForm::Lang("1")));
+        assert_eq!(KeyForm::of("raw(57)"), Some(KeyForm::Raw("57)")));
+        assert_eq!(KeyForm::of("0x39"), Some(KeyForm::Hex));
+        assert_eq!(KeyForm::of("fn"), None);
+    }
+
+    #[test]
+    fn mappings_pairs() {
+        let pairs = [(Key::CapsLock, Key::Escape), (Key::Fn, Key::LeftControl)];
+        let mappings = Mappings::from_pairs(&pairs);
+        assert_eq!(
+            mappings,
+            Mappings(vec![
+                Map(Key::CapsLock, Key::Escape),
+                Map(Key::Fn, Key::LeftControl)
+            ])
+        );
+        assert_eq!(mappings.to_pairs(), pairs);
+        assert_eq!(
+            serde_json::to_string(&mappings.0).unwrap(),
+            r#"[{"src":"capslock","dst":"escape"},{"src":"fn","dst":"lcontrol"}]"#
+        );
+    }
+
+    #[test]
+    fn map_new() {
+        let map = Map::new(Key::CapsLock, Key::Escape);
+        assert_eq!(map.src(), Key::CapsLock);
+        assert_eq!(map.dst(), Key::Escape);
+        assert_eq!(map, Map(Key::CapsLock, Key::Escape));
+        assert_eq!(map.swapped(), Map::new(Key::Escape, Key::CapsLock));
+    }
+
+    #[test]
+    fn mappings_sort() {
+        let parse = |ms: &[&str]| {
+            let mut mappings: Vec<_> = ms
+                .iter()
+                .flat_map(|m| Mappings::from_str(m).unwrap().0)
+                .collect();
+            mappings.sort_by_key(sort_key);
+            mappings
+        };
+        let a = parse(&["fn:lcontrol", "capslock:escape", "a:b"]);
+        let b = parse(&["a:b", "capslock:escape", "fn:lcontrol"]);
+        assert_eq!(a, b);
+        assert_eq!(
+            a,
+            [
+                Map(Key::Char('a'), Key::Char('b')),
+                Map(Key::CapsLock, Key::Escape),
+                Map(Key::Fn, Key::LeftControl),
+            ]
+        );
+    }
+
     #[test]
     fn key_from_str() {
         assert_eq!(Key::from_str("return").unwrap(), Key::Return);
         assert_eq!(Key::from_str("escape").unwrap(), Key::Escape);
         assert_eq!(Key::from_str("delete").unwrap(), Key::Delete);
+        assert_eq!(Key::from_str("backspace").unwrap(), Key::Delete);
+        assert_eq!(Key::from_str("forwarddelete").unwrap(), Key::ForwardDelete);
         assert_eq!(Key::from_str("capslock").unwrap(), Key::CapsLock);
         assert_eq!(Key::from_str("lcontrol").unwrap(), Key::LeftControl);
         assert_eq!(Key::from_str("rcontrol").unwrap(), Key::RightControl);
@@ -323,21 +1215,321 @@ mod tests {
         assert_eq!(Key::from_str("lcommand").unwrap(), Key::LeftCommand);
         assert_eq!(Key::from_str("rcommand").unwrap(), Key::RightCommand);
         assert_eq!(Key::from_str("fn").unwrap(), Key::Fn);
+        assert_eq!(Key::from_str("right").unwrap(), Key::Right);
+        assert_eq!(Key::from_str("left").unwrap(), Key::Left);
+        assert_eq!(Key::from_str("down").unwrap(), Key::Down);
+        assert_eq!(Key::from_str("Up").unwrap(), Key::Up);
+        assert_eq!(Key::from_str("left+control").unwrap(), Key::LeftControl);
+        assert_eq!(Key::from_str("right+control").unwrap(), Key::RightControl);
+        assert_eq!(Key::from_str("left+shift").unwrap(), Key::LeftShift);
+        assert_eq!(Key::from_str("right+shift").unwrap(), Key::RightShift);
+        assert_eq!(Key::from_str("left+option").unwrap(), Key::LeftOption);
+        assert_eq!(Key::from_str("right+option").unwrap(), Key::RightOption);
+        assert_eq!(Key::from_str("left+command").unwrap(), Key::LeftCommand);
+        assert_eq!(Key::from_str("Right+Command").unwrap(), Key::RightCommand);
         for f in 1..=24 {
             assert_eq!(Key::from_str(&format!("f{}", f)).unwrap(), Key::F(f));
         }
+        for l in 1..=9 {
+            assert_eq!(Key::from_str(&format!("lang{}", l)).unwrap(), Key::Lang(l));
+        }
+        assert_eq!(Key::from_str("hangul").unwrap(), Key::Lang(1));
+        assert_eq!(Key::from_str("hanja").unwrap(), Key::Lang(2));
+        assert_eq!(Key::from_str("kana").unwrap(), Key::Lang(1));
+        assert_eq!(Key::from_str("eisu").unwrap(), Key::Lang(2));
+        assert!(Key::from_str("lang10").is_err());
         assert_eq!(Key::from_str("c").unwrap(), Key::Char('c'));
         assert_eq!(Key::from_str("0x39").unwrap(), Key::Raw(0x39));
     }
 
+    #[test]
+    fn key_from_str_qmk() {
+        assert_eq!(Key::from_str("KC_ESC").unwrap(), Key::Escape);
+        assert_eq!(Key::from_str("KC_CAPS").unwrap(), Key::CapsLock);
+        assert_eq!(Key::from_str("KC_LCTL").unwrap(), Key::LeftControl);
+        assert_eq!(Key::from_str("KC_RGUI").unwrap(), Key::RightCommand);
+        assert_eq!(Key::from_str("KC_LEFT_ALT").unwrap(), Key::LeftOption);
+        assert_eq!(Key::from_str("KC_BSPC").unwrap(), Key::Delete);
+        assert_eq!(Key::from_str("KC_GRV").unwrap(), Key::Char('`'));
+        assert_eq!(Key::from_str("KC_A").unwrap(), Key::Char('a'));
+        assert_eq!(Key::from_str("KC_1").unwrap(), Key::Char('1'));
+        assert_eq!(Key::from_str("KC_F13").unwrap(), Key::F(13));
+        assert_eq!(Key::from_str("KC_NUBS").unwrap(), Key::Raw(0x64));
+        assert_eq!(Key::from_str("KC_RGHT").unwrap(), Key::Right);
+        assert_eq!(Key::from_str("KC_UP").unwrap(), Key::Up);
+        assert_eq!(Key::from_str("KC_VOLU").unwrap(), Key::VolumeUp);
+        assert_eq!(Key::from_str("KC_MPLY").unwrap(), Key::PlayPause);
+        assert!(Key::from_str("KC_F25").is_err());
+        assert!(Key::from_str("KC_NOPE").is_err());
+    }
+
+    #[test]
+    fn key_from_str_tn2450() {
+        assert_eq!(
+            Key::from_str("Keyboard Left GUI").unwrap(),
+            Key::LeftCommand
+        );
+        assert_eq!(
+            Key::from_str("Keyboard RightAlt").unwrap(),
+            Key::RightOption
+        );
+        assert_eq!(
+            Key::from_str("Keyboard Left Control").unwrap(),
+            Key::LeftControl
+        );
+        assert_eq!(Key::from_str("Keyboard Caps Lock").unwrap(), Key::CapsLock);
+        assert_eq!(
+            Key::from_str("Keyboard DELETE (Backspace)").unwrap(),
+            Key::Delete
+        );
+        assert_eq!(Key::from_str("Keyboard a and A").unwrap(), Key::Char('a'));
+        assert_eq!(
+            Key::from_str("Keyboard - and (underscore)").unwrap(),
+            Key::Char('-')
+        );
+        assert_eq!(
+            Key::from_str("Keyboard Grave Accent and Tilde").unwrap(),
+            Key::Char('`')
+        );
+        assert_eq!(Key::from_str("Keyboard F13").unwrap(), Key::F(13));
+        assert_eq!(Key::from_str("Keyboard LANG2").unwrap(), Key::Lang(2));
+        assert_eq!(
+            Key::from_str("Keyboard Non-US \\ and |").unwrap(),
+            Key::Raw(0x64)
+        );
+        assert!(Key::from_str("Keyboard F25").is_err());
+        assert!(Key::from_str("Keyboard Nope").is_err());
+    }
+
+    #[test]
+    fn key_try_from_usage() {
+        let usage = |k: Key| k.usage_page_id() + k.usage_id().unwrap();
+        for key in [
+            Key::Return,
+            Key::LeftCommand,
+            Key::Fn,
+            Key::VolumeDown,
+            Key::Mute,
+            Key::Char('a'),
+            Key::Char('\t'),
+            Key::Char('/'),
+            Key::F(13),
+            Key::Lang(2),
+        ] {
+            assert_eq!(Key::try_from(usage(key)).unwrap(), key);
+        }
+        assert_eq!(Key::try_from(0x7000000e3).unwrap(), Key::LeftCommand);
+
+        // unknown usages on the keyboard page
+        assert_eq!(Key::try_from(0x700000064).unwrap(), Key::Raw(0x64));
+
+        // consumer page
+        assert_eq!(Key::try_from(0xc000000e9).unwrap(), Key::VolumeUp);
+        assert_eq!(Key::try_from(0xc000000cd).unwrap(), Key::PlayPause);
+        assert_eq!(Key::try_from(0xc000000b5).unwrap(), Key::NextTrack);
+        let key = Key::try_from(0xc000000b8).unwrap();
+        assert_eq!(key, Key::Raw(0xc000000b8));
+        assert_eq!(usage(key), 0xc000000b8);
+
+        assert_eq!(
+            Key::try_from(0x39).unwrap_err().to_string(),
+            "`0x39` does not include a usage page"
+        );
+    }
+
+    #[test]
+    fn key_from_usage() {
+        assert_eq!(Key::from_usage(0x7_0000_0000, 0x39), Key::CapsLock);
+        assert_eq!(Key::from_usage(0x7_0000_0000, 0x68), Key::F(13));
+        assert_eq!(Key::from_usage(0x7_0000_0000, 0x64), Key::Raw(0x64));
+        assert_eq!(Key::from_usage(0xc_0000_0000, 0xcd), Key::PlayPause);
+        assert_eq!(Key::from_usage(0xc_0000_0000, 0xb5), Key::NextTrack);
+        assert_eq!(Key::from_usage(0xc_0000_0000, 0xb8), Key::Raw(0xc000000b8));
+        assert_eq!(Key::from_usage(0xff_0000_0000, 0x03), Key::Fn);
+        assert_eq!(Key::Raw(0x700000039).named(), Key::CapsLock);
+        assert_eq!(Key::Raw(0x68).named(), Key::F(13));
+        assert_eq!(Key::Raw(0xc000000b8).named(), Key::Raw(0xc000000b8));
+        assert_eq!(Key::Escape.named(), Key::Escape);
+    }
+
+    #[test]
+    fn key_from_str_raw_literal() {
+        let key = Key::from_str("Raw(30064771129)").unwrap();
+        assert_eq!(key, Key::Raw(0x700000039));
+        assert_eq!(Key::from_str("raw(0x700000039)").unwrap(), key);
+        assert_eq!(key.named(), Key::CapsLock);
+        // the usage page is not added a second time
+        assert_eq!(key.usage_page_id() + key.usage_id().unwrap(), 0x700000039);
+        assert_eq!(
+            Mappings::from_str("Raw(30064771129):Raw(30064771113)")
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|Map(src, dst)| Map(src.named(), dst.named()))
+                .collect::<Vec<_>>(),
+            Mappings::from_str("capslock:escape").unwrap().0
+        );
+        assert_eq!(
+            Key::from_str("Raw(caps)").unwrap_err().to_string(),
+            "failed to parse `caps` as an integer"
+        );
+    }
+
+    #[test]
+    fn key_display() {
+        assert_eq!(Key::CapsLock.to_string(), "CapsLock");
+        assert_eq!(Key::Escape.to_string(), "Escape");
+        assert_eq!(Key::F(5).to_string(), "F5");
+        assert_eq!(Key::Char('a').to_string(), "a");
+        assert_eq!(Key::Char(' ').to_string(), "Space");
+        assert_eq!(Key::Keypad('+').to_string(), "Keypad+");
+        assert_eq!(Key::Raw(0x700000039).to_string(), "0x700000039");
+    }
+
+    #[test]
+    fn key_code_round_trip() {
+        let keys = [
+            Key::Return,
+            Key::Escape,
+            Key::Delete,
+            Key::ForwardDelete,
+            Key::CapsLock,
+            Key::LeftControl,
+            Key::LeftShift,
+            Key::LeftOption,
+            Key::LeftCommand,
+            Key::RightControl,
+            Key::RightShift,
+            Key::RightOption,
+            Key::RightCommand,
+            Key::Fn,
+            Key::Right,
+            Key::Left,
+            Key::Down,
+            Key::Up,
+            Key::VolumeUp,
+            Key::VolumeDown,
+            Key::Mute,
+            Key::PlayPause,
+            Key::NextTrack,
+            Key::PrevTrack,
+            Key::BrightnessUp,
+            Key::BrightnessDown,
+            Key::Char('a'),
+            Key::Char(' '),
+            Key::F(13),
+            Key::Lang(2),
+            Key::Raw(0x64),
+        ];
+        for key in keys {
+            assert_eq!(Key::from_str(&key.code()).unwrap(), key);
+        }
+    }
+
     #[test]
     fn key_usage_id() {
         assert_eq!(Key::Return.usage_id().unwrap(), 0x28);
         assert_eq!(Key::Escape.usage_id().unwrap(), 0x29);
         assert_eq!(Key::Delete.usage_id().unwrap(), 0x2a);
+        assert_eq!(
+            Key::from_str("backspace").unwrap().usage_id().unwrap(),
+            0x2a
+        );
+        assert_eq!(
+            Key::from_str("forwarddelete").unwrap().usage_id().unwrap(),
+            0x4c
+        );
         assert_eq!(Key::CapsLock.usage_id().unwrap(), 0x39);
+        assert_eq!(Key::Right.usage_id().unwrap(), 0x4f);
+        assert_eq!(Key::Left.usage_id().unwrap(), 0x50);
+        assert_eq!(Key::Down.usage_id().unwrap(), 0x51);
+        assert_eq!(Key::Up.usage_id().unwrap(), 0x52);
         assert_eq!(Key::F(11).usage_id().unwrap(), 0x44);
         assert_eq!(Key::Char('a').usage_id().unwrap(), 0x04);
+        assert_eq!(Key::from_str("hangul").unwrap().usage_id().unwrap(), 0x90);
+        assert_eq!(Key::from_str("hanja").unwrap().usage_id().unwrap(), 0x91);
+        assert_eq!(Key::Lang(9).usage_id().unwrap(), 0x98);
+        assert_eq!(Key::Lang(0).usage_id(), None);
+        assert_eq!(Key::Lang(10).usage_id(), None);
+        assert_eq!(Key::F(0).usage_id(), None);
+        assert_eq!(Key::F(25).usage_id(), None);
         assert_eq!(Key::Raw(0x5).usage_id().unwrap(), 0x5);
     }
+
+    #[test]
+    fn key_usage_keypad() {
+        // USB HID Usage Tables, Section 10 Keyboard/Keypad Page
+        for (s, key, usage_id) in [
+            ("numlock", Key::NumLock, 0x53),
+            ("kpslash", Key::Keypad('/'), 0x54),
+            ("kpasterisk", Key::Keypad('*'), 0x55),
+            ("kpminus", Key::Keypad('-'), 0x56),
+            ("kpplus", Key::Keypad('+'), 0x57),
+            ("kpenter", Key::KeypadEnter, 0x58),
+            ("kp1", Key::Keypad('1'), 0x59),
+            ("kp2", Key::Keypad('2'), 0x5a),
+            ("kp3", Key::Keypad('3'), 0x5b),
+            ("kp4", Key::Keypad('4'), 0x5c),
+            ("kp5", Key::Keypad('5'), 0x5d),
+            ("kp6", Key::Keypad('6'), 0x5e),
+            ("kp7", Key::Keypad('7'), 0x5f),
+            ("kp8", Key::Keypad('8'), 0x60),
+            ("kp9", Key::Keypad('9'), 0x61),
+            ("kp0", Key::Keypad('0'), 0x62),
+            ("kpdot", Key::Keypad('.'), 0x63),
+        ] {
+            assert_eq!(Key::from_str(s).unwrap(), key);
+            assert_eq!(key.code(), s);
+            assert_eq!(key.usage_page_id(), 0x7_0000_0000);
+            assert_eq!(key.usage_id().unwrap(), usage_id);
+            assert_eq!(Key::try_from(0x7_0000_0000 + usage_id).unwrap(), key);
+        }
+        assert_eq!(Key::from_str("KC_P7").unwrap(), Key::Keypad('7'));
+        assert_eq!(Key::from_str("KC_PENT").unwrap(), Key::KeypadEnter);
+        assert_eq!(Key::from_str("KC_NUM").unwrap(), Key::NumLock);
+        assert_eq!(Key::from_str("Keypad 1 and End").unwrap(), Key::Keypad('1'));
+        assert_eq!(Key::from_str("Keypad *").unwrap(), Key::Keypad('*'));
+        assert_eq!(Key::from_str("Keypad ENTER").unwrap(), Key::KeypadEnter);
+        assert_eq!(
+            Key::from_str("Keypad NumLock and Clear").unwrap(),
+            Key::NumLock
+        );
+        assert!(Key::from_str("kp10").is_err());
+        assert_eq!(Key::Keypad('a').usage_id(), None);
+    }
+
+    #[test]
+    fn key_usage_consumer() {
+        assert_eq!(Key::from_str("volumeup").unwrap(), Key::VolumeUp);
+        assert_eq!(Key::from_str("VolumeDown").unwrap(), Key::VolumeDown);
+        assert_eq!(Key::from_str("mute").unwrap(), Key::Mute);
+        assert_eq!(Key::from_str("playpause").unwrap(), Key::PlayPause);
+        assert_eq!(Key::from_str("KC_MNXT").unwrap(), Key::NextTrack);
+        assert_eq!(
+            Key::from_str("KC_BRIGHTNESS_DOWN").unwrap(),
+            Key::BrightnessDown
+        );
+        for (key, usage_id) in [
+            (Key::VolumeUp, 0xe9),
+            (Key::VolumeDown, 0xea),
+            (Key::Mute, 0xe2),
+            (Key::PlayPause, 0xcd),
+            (Key::NextTrack, 0xb5),
+            (Key::PrevTrack, 0xb6),
+            (Key::BrightnessUp, 0x6f),
+            (Key::BrightnessDown, 0x70),
+        ] {
+            assert_eq!(key.usage_page_id(), 0xc_0000_0000);
+            assert_eq!(key.usage_id().unwrap(), usage_id);
+        }
+    }
+
+    #[test]
+    fn key_usage_page_id_qualified_raw() {
+        let key = Key::from_str("0x700000039").unwrap();
+        assert_eq!(key.usage_page_id(), 0);
+        assert_eq!(key.usage_page_id() + key.usage_id().unwrap(), 0x700000039);
+        let key = Key::from_str("0x39").unwrap();
+        assert_eq!(key.usage_page_id() + key.usage_id().unwrap(), 0x700000039);
+    }
 }
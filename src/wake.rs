@@ -0,0 +1,106 @@
+use std::sync::mpsc::Receiver;
+
+/// Listen for the system waking from sleep.
+///
+/// Returns a receiver that gets a message each time the system wakes, or
+/// `None` if notifications are not available, in which case the caller should
+/// fall back to polling. Notifications are only available on macOS when built
+/// with the `wake` feature.
+pub fn listen() -> Option<Receiver<()>> {
+    imp::listen()
+}
+
+#[cfg(all(feature = "wake", target_os = "macos"))]
+mod imp {
+    use std::ffi::c_void;
+    use std::ptr;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+
+    type IoObject = u32;
+    type IONotificationPortRef = *mut c_void;
+    type IOServiceInterestCallback = extern "C" fn(*mut c_void, IoObject, u32, *mut c_void);
+    type CFRunLoopRef = *mut c_void;
+    type CFRunLoopSourceRef = *mut c_void;
+    type CFStringRef = *const c_void;
+
+    // https://developer.apple.com/documentation/iokit/iomessage_h
+    const IO_MESSAGE_CAN_SYSTEM_SLEEP: u32 = 0xe000_0270;
+    const IO_MESSAGE_SYSTEM_WILL_SLEEP: u32 = 0xe000_0280;
+    const IO_MESSAGE_SYSTEM_HAS_POWERED_ON: u32 = 0xe000_0300;
+
+    #[link(name = "IOKit", kind = "framework")]
+    extern "C" {
+        fn IORegisterForSystemPower(
+            refcon: *mut c_void,
+            port: *mut IONotificationPortRef,
+            callback: IOServiceInterestCallback,
+            notifier: *mut IoObject,
+        ) -> IoObject;
+        fn IOAllowPowerChange(kernel_port: IoObject, notification_id: isize) -> i32;
+        fn IONotificationPortGetRunLoopSource(notify: IONotificationPortRef) -> CFRunLoopSourceRef;
+    }
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        static kCFRunLoopCommonModes: CFStringRef;
+        fn CFRunLoopGetCurrent() -> CFRunLoopRef;
+        fn CFRunLoopAddSource(rl: CFRunLoopRef, source: CFRunLoopSourceRef, mode: CFStringRef);
+        fn CFRunLoopRun();
+    }
+
+    struct State {
+        root_port: IoObject,
+        tx: Sender<()>,
+    }
+
+    extern "C" fn callback(refcon: *mut c_void, _: IoObject, message: u32, argument: *mut c_void) {
+        // SAFETY: the refcon is the leaked `State` registered below
+        let state = unsafe { &*(refcon as *const State) };
+        match message {
+            // the system waits for every registered client to allow sleep
+            IO_MESSAGE_CAN_SYSTEM_SLEEP | IO_MESSAGE_SYSTEM_WILL_SLEEP => unsafe {
+                IOAllowPowerChange(state.root_port, argument as isize);
+            },
+            IO_MESSAGE_SYSTEM_HAS_POWERED_ON => {
+                let _ = state.tx.send(());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn listen() -> Option<Receiver<()>> {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        thread::spawn(move || unsafe {
+            // the state lives as long as the run loop which runs forever
+            let state = Box::into_raw(Box::new(State { root_port: 0, tx }));
+            let mut port = ptr::null_mut();
+            let mut notifier = 0;
+            let root_port =
+                IORegisterForSystemPower(state.cast(), &mut port, callback, &mut notifier);
+            if root_port == 0 {
+                let _ = ready_tx.send(false);
+                return;
+            }
+            (*state).root_port = root_port;
+            CFRunLoopAddSource(
+                CFRunLoopGetCurrent(),
+                IONotificationPortGetRunLoopSource(port),
+                kCFRunLoopCommonModes,
+            );
+            let _ = ready_tx.send(true);
+            CFRunLoopRun();
+        });
+        ready_rx.recv().unwrap_or(false).then_some(rx)
+    }
+}
+
+#[cfg(not(all(feature = "wake", target_os = "macos")))]
+mod imp {
+    use std::sync::mpsc::Receiver;
+
+    pub fn listen() -> Option<Receiver<()>> {
+        None
+    }
+}